@@ -3,132 +3,108 @@ use std::collections::VecDeque;
 use std::fmt;
 
 /// Find the location of the first crash.
-pub fn first_crash(input: &str) -> Point {
-    let mut grids: Vec<Vec<Grid>> = input
-        .split('\n')
-        .filter(|s| !s.is_empty())
-        .map(|r| r.chars().map(Grid::new).collect())
-        .collect();
-    // Pad a border.
-    let columns = grids[0].len();
-    for r in &mut grids {
-        r.insert(0, Grid::NA);
-        r.push(Grid::NA);
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day13::{first_crash, SimulationError};
+///
+/// assert_eq!(
+///     Err(SimulationError::NoCrash { period: 10 }),
+///     first_crash("/>-\\\n|  |\n\\--/")
+/// );
+/// ```
+pub fn first_crash(input: &str) -> Result<Point, SimulationError> {
+    let mut system = CartSystem::new(input).map_err(SimulationError::InvalidTrack)?;
+    loop {
+        if let Some(crash) = system.step().into_iter().next() {
+            return Ok(crash.point);
+        }
+        if let Some(period) = system.period() {
+            return Err(SimulationError::NoCrash { period });
+        }
     }
-    grids.insert(0, vec![Grid::NA; columns + 2]);
-    grids.push(vec![Grid::NA; columns + 2]);
+}
 
-    let mut carts: Vec<Cart> = input
-        .trim()
-        .split('\n')
-        .enumerate()
-        .map(|(i, r)| {
-            r.chars()
-                .enumerate()
-                .map(|(j, c)| Cart::try_new(c, j, i))
-                .collect::<Vec<_>>()
-        })
-        .flatten()
-        .filter_map(|o| o)
-        .collect();
+/// Find the location of the last remaining cart at the end of the first tick
+/// in which at most one cart is left.
+pub fn last_cart_location(input: &str) -> Result<Point, SimulationError> {
+    let mut system = CartSystem::new(input).map_err(SimulationError::InvalidTrack)?;
     loop {
-        // print_state(&grids, &carts);
-        carts.sort_by(|a, b| (a.point.y * 1000 + a.point.x).cmp(&(b.point.y * 1000 + b.point.x)));
-        for cart in &mut carts {
-            cart.next(&grids);
+        system.step();
+        if system.carts().len() <= 1 {
+            break;
         }
-        if let Some(p) = duplicate(
-            &carts
-                .iter()
-                .map(|c| Point {
-                    x: c.point.x,
-                    y: c.point.y,
-                })
-                .collect::<Vec<_>>(),
-        ) {
-            return Point {
-                x: p.x - 1,
-                y: p.y - 1,
-            };
+        if let Some(period) = system.period() {
+            return Err(SimulationError::NoCrash { period });
         }
     }
+    match system.carts().first() {
+        Some(cart) => Ok(cart.position()),
+        None => Err(SimulationError::NoCartLeft),
+    }
 }
 
-/// Find the location of the last remaining cart.
-pub fn last_cart_location(input: &str) -> Point {
-    let mut grids: Vec<Vec<Grid>> = input
-        .split('\n')
-        .filter(|s| !s.is_empty())
-        .map(|r| r.chars().map(Grid::new).collect())
-        .collect();
-    // Pad a border.
-    let columns = grids[0].len();
-    for r in &mut grids {
-        r.insert(0, Grid::NA);
-        r.push(Grid::NA);
-    }
-    grids.insert(0, vec![Grid::NA; columns + 2]);
-    grids.push(vec![Grid::NA; columns + 2]);
+/// Why a simulation has no answer.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SimulationError {
+    /// The carts return to an earlier state every `period` ticks, so no
+    /// further crash will ever happen.
+    NoCrash { period: usize },
+    /// Every cart crashed.
+    NoCartLeft,
+    /// The track has problems that `validate` found.
+    InvalidTrack(Vec<Diagnostic>),
+}
 
-    let mut carts: Vec<Cart> = input
-        .trim()
-        .split('\n')
-        .enumerate()
-        .map(|(i, r)| {
-            r.chars()
-                .enumerate()
-                .map(|(j, c)| Cart::try_new(c, j, i))
-                .collect::<Vec<_>>()
-        })
-        .flatten()
-        .filter_map(|o| o)
-        .collect();
-    loop {
-        // print_state(&grids, &carts);
-        let mut removed = HashSet::new();
-        carts.sort_by(|a, b| (a.point.y * 1000 + a.point.x).cmp(&(b.point.y * 1000 + b.point.x)));
-        for i in 0..carts.len() {
-            if removed.contains(&i) {
-                continue;
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulationError::NoCrash { period } => {
+                write!(f, "no crash, the carts repeat every {} ticks", period)
             }
-            carts[i].next(&grids);
-            for d in duplicates(
-                &carts
-                    .iter()
-                    .enumerate()
-                    .filter(|(i, _)| !removed.contains(i))
-                    .map(|(_, c)| Point {
-                        x: c.point.x,
-                        y: c.point.y,
-                    })
-                    .collect::<Vec<_>>(),
-            ) {
-                for (j, c) in &mut carts.iter().enumerate() {
-                    if c.point == d {
-                        removed.insert(j);
-                    }
+            SimulationError::NoCartLeft => write!(f, "no cart left"),
+            SimulationError::InvalidTrack(diagnostics) => {
+                write!(f, "invalid track:")?;
+                for d in diagnostics {
+                    write!(f, "\n{}", d)?;
                 }
+                Ok(())
             }
         }
-        carts = carts
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| !removed.contains(i))
-            .map(|(_, c)| c.clone())
-            .collect::<Vec<_>>();
-        if carts.len() == 1 {
-            return Point {
-                x: carts[0].point.x - 1,
-                y: carts[0].point.y - 1,
-            };
-        }
     }
 }
 
+/// All crashes in the order they happen, until at most one cart is left or
+/// the carts repeat their movements without crashing. Returns the problems
+/// of the track if it is not valid.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day13::{crashes, Crash, Point};
+///
+/// let crashes = crashes("/>-<\\
+/// |   |
+/// \\>-</").unwrap();
+/// assert_eq!(vec![
+///     Crash { tick: 1, point: Point { x: 2, y: 0 }, carts: vec![1, 0] },
+///     Crash { tick: 1, point: Point { x: 2, y: 2 }, carts: vec![3, 2] },
+/// ], crashes);
+/// ```
+pub fn crashes(input: &str) -> Result<Vec<Crash>, Vec<Diagnostic>> {
+    let mut system = CartSystem::new(input)?;
+    let mut crashes = vec![];
+    while system.carts().len() > 1 && system.period().is_none() {
+        crashes.extend(system.step());
+    }
+    Ok(crashes)
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Point {
-    x: usize,
-    y: usize,
+    pub x: usize,
+    pub y: usize,
 }
 
 impl fmt::Display for Point {
@@ -137,6 +113,400 @@ impl fmt::Display for Point {
     }
 }
 
+/// Two carts that ran into each other.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Crash {
+    /// Number of the tick in which the carts crashed, starting at 1.
+    pub tick: usize,
+    pub point: Point,
+    /// Ids of the cart that moved and of the cart that it ran into.
+    pub carts: Vec<usize>,
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let carts: Vec<String> = self.carts.iter().map(|c| c.to_string()).collect();
+        write!(
+            f,
+            "tick {}: {} (carts {})",
+            self.tick,
+            self.point,
+            carts.join(", ")
+        )
+    }
+}
+
+/// Carts moving on a track. Crashed carts are removed right away.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day13::{CartSystem, Orientation, Point};
+///
+/// let mut system = CartSystem::new("/->-\\
+/// |   |
+/// \\-<-/").unwrap();
+/// assert!(system.step().is_empty());
+/// let carts = system.carts();
+/// assert_eq!(Point { x: 3, y: 0 }, carts[0].position());
+/// assert_eq!(Orientation::Right, carts[0].orientation());
+/// assert_eq!(1, system.tick());
+/// ```
+pub struct CartSystem {
+    grid: Vec<Vec<Grid>>,
+    carts: Vec<Cart>,
+    tick: usize,
+    /// State that later states are compared against to find a cycle with
+    /// Brent's algorithm, and the number of ticks since it was taken.
+    snapshot: Vec<(Point, Orientation, Decision)>,
+    since_snapshot: usize,
+    /// Number of ticks after which the snapshot is replaced.
+    power: usize,
+    period: Option<usize>,
+}
+
+impl CartSystem {
+    /// Read the track and the carts on it, unless `validate` finds problems
+    /// with the track.
+    pub fn new(input: &str) -> Result<Self, Vec<Diagnostic>> {
+        let diagnostics = validate(input);
+        if diagnostics.is_empty() {
+            Ok(CartSystem::parse(input))
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    fn parse(input: &str) -> Self {
+        let mut grid: Vec<Vec<Grid>> = input
+            .split('\n')
+            .filter(|s| !s.is_empty())
+            .map(|r| r.chars().map(Grid::new).collect())
+            .collect();
+        // Pad a border.
+        let columns = grid.iter().map(Vec::len).max().unwrap_or(0);
+        for r in &mut grid {
+            r.insert(0, Grid::NA);
+            r.resize(columns + 2, Grid::NA);
+        }
+        grid.insert(0, vec![Grid::NA; columns + 2]);
+        grid.push(vec![Grid::NA; columns + 2]);
+
+        let mut carts: Vec<Cart> = input
+            .split('\n')
+            .filter(|s| !s.is_empty())
+            .enumerate()
+            .flat_map(|(i, r)| {
+                r.chars()
+                    .enumerate()
+                    .filter_map(move |(j, c)| Cart::try_new(c, j, i))
+            })
+            .collect();
+        for (id, cart) in carts.iter_mut().enumerate() {
+            cart.id = id;
+        }
+        let mut system = CartSystem {
+            grid,
+            carts,
+            tick: 0,
+            snapshot: vec![],
+            since_snapshot: 0,
+            power: 1,
+            period: None,
+        };
+        system.snapshot = system.state();
+        system
+    }
+
+    /// Number of ticks so far.
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    /// Number of ticks after which the carts are back in the same state,
+    /// once that has been noticed, which may take a few cycles. From then on
+    /// they repeat their movements forever.
+    pub fn period(&self) -> Option<usize> {
+        self.period
+    }
+
+    /// Positions, orientations and next decisions of the carts.
+    fn state(&self) -> Vec<(Point, Orientation, Decision)> {
+        let mut state: Vec<_> = self
+            .carts
+            .iter()
+            .map(|c| (c.point.clone(), c.orientation, c.decisions[0].clone()))
+            .collect();
+        state.sort_by_key(|(p, _, _)| (p.y, p.x));
+        state
+    }
+
+    /// The carts that have not crashed, in the order in which they move.
+    pub fn carts(&self) -> &[Cart] {
+        &self.carts
+    }
+
+    /// Move every cart once, in reading order of their positions, and return
+    /// the crashes in the order they happened.
+    pub fn step(&mut self) -> Vec<Crash> {
+        self.tick += 1;
+        let mut crashes = vec![];
+        let mut removed = HashSet::new();
+        self.carts.sort_by_key(|c| (c.point.y, c.point.x));
+        for i in 0..self.carts.len() {
+            if removed.contains(&i) {
+                continue;
+            }
+            self.carts[i].next(&self.grid);
+            let point = self.carts[i].point.clone();
+            let other = (0..self.carts.len())
+                .find(|&j| j != i && !removed.contains(&j) && self.carts[j].point == point);
+            if let Some(j) = other {
+                removed.insert(i);
+                removed.insert(j);
+                crashes.push(Crash {
+                    tick: self.tick,
+                    point: Point {
+                        x: point.x - 1,
+                        y: point.y - 1,
+                    },
+                    carts: vec![self.carts[i].id, self.carts[j].id],
+                });
+            }
+        }
+        let mut i = 0;
+        self.carts.retain(|_| {
+            i += 1;
+            !removed.contains(&(i - 1))
+        });
+        if self.period.is_none() {
+            self.since_snapshot += 1;
+            let state = self.state();
+            if state == self.snapshot {
+                self.period = Some(self.since_snapshot);
+            } else if self.since_snapshot == self.power {
+                self.snapshot = state;
+                self.since_snapshot = 0;
+                self.power *= 2;
+            }
+        }
+        crashes
+    }
+}
+
+/// A structural problem of a track.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Problem {
+    /// A character that is neither track nor cart.
+    UnknownCharacter(char),
+    /// The track does not continue in the direction.
+    Dangling(Orientation),
+    /// A curve that does not join a horizontal and a vertical track.
+    OpenCurve,
+    /// A cart that starts on a curve rather than on a straight track.
+    CartOnCurve,
+    /// A cart that starts on an intersection rather than on a straight track.
+    CartOnIntersection,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::UnknownCharacter(c) => write!(f, "unknown character '{}'", c),
+            Problem::Dangling(o) => write!(
+                f,
+                "track ends {}",
+                match o {
+                    Orientation::Left => "to the left",
+                    Orientation::Right => "to the right",
+                    Orientation::Up => "above",
+                    Orientation::Down => "below",
+                }
+            ),
+            Problem::OpenCurve => write!(f, "curve does not join two tracks"),
+            Problem::CartOnCurve => write!(f, "cart starts on a curve"),
+            Problem::CartOnIntersection => write!(f, "cart starts on an intersection"),
+        }
+    }
+}
+
+/// A problem at a position of a track.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub point: Point,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.point, self.problem)
+    }
+}
+
+const DIRECTIONS: [Orientation; 4] = [
+    Orientation::Up,
+    Orientation::Left,
+    Orientation::Right,
+    Orientation::Down,
+];
+
+impl Orientation {
+    fn opposite(self) -> Self {
+        match self {
+            Orientation::Left => Orientation::Right,
+            Orientation::Right => Orientation::Left,
+            Orientation::Up => Orientation::Down,
+            Orientation::Down => Orientation::Up,
+        }
+    }
+
+    fn is_horizontal(self) -> bool {
+        matches!(self, Orientation::Left | Orientation::Right)
+    }
+
+    /// The neighbour of the position in this direction.
+    fn neighbour(self, x: i64, y: i64) -> (i64, i64) {
+        match self {
+            Orientation::Left => (x - 1, y),
+            Orientation::Right => (x + 1, y),
+            Orientation::Up => (x, y - 1),
+            Orientation::Down => (x, y + 1),
+        }
+    }
+}
+
+/// The directions in which the track leaves a cell. Curves may leave in any
+/// direction until it is known which tracks they join.
+fn exits(c: char) -> Vec<Orientation> {
+    match c {
+        '-' | '<' | '>' => vec![Orientation::Left, Orientation::Right],
+        '|' | '^' | 'v' => vec![Orientation::Up, Orientation::Down],
+        '+' | '/' | '\\' => DIRECTIONS.to_vec(),
+        _ => vec![],
+    }
+}
+
+/// Find every structural problem of the track, in reading order.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day13::{validate, Orientation, Point, Problem};
+///
+/// assert!(validate("/->-\\\n\\-<-/").is_empty());
+/// let diagnostics = validate("/->-\\\n\\-<--");
+/// assert_eq!(3, diagnostics.len());
+/// assert_eq!(Point { x: 4, y: 0 }, diagnostics[1].point);
+/// assert_eq!(Problem::OpenCurve, diagnostics[1].problem);
+/// assert_eq!(Problem::Dangling(Orientation::Right), diagnostics[2].problem);
+/// ```
+pub fn validate(input: &str) -> Vec<Diagnostic> {
+    let map: Vec<Vec<char>> = input
+        .split('\n')
+        .filter(|s| !s.is_empty())
+        .map(|r| r.chars().collect())
+        .collect();
+    let at = |x: i64, y: i64| -> char {
+        if x < 0 || y < 0 {
+            return ' ';
+        }
+        map.get(y as usize)
+            .and_then(|r| r.get(x as usize))
+            .cloned()
+            .unwrap_or(' ')
+    };
+
+    // Decide which tracks every curve joins.
+    let mut resolved: Vec<Vec<Vec<Orientation>>> = vec![];
+    for (y, row) in map.iter().enumerate() {
+        let mut cells = vec![];
+        for (x, &c) in row.iter().enumerate() {
+            let (x, y) = (x as i64, y as i64);
+            let pairs = match c {
+                '/' => [
+                    [Orientation::Left, Orientation::Up],
+                    [Orientation::Right, Orientation::Down],
+                ],
+                '\\' => [
+                    [Orientation::Left, Orientation::Down],
+                    [Orientation::Right, Orientation::Up],
+                ],
+                _ => {
+                    cells.push(exits(c));
+                    continue;
+                }
+            };
+            let joined = pairs.iter().find(|pair| {
+                pair.iter().all(|d| {
+                    let (nx, ny) = d.neighbour(x, y);
+                    exits(at(nx, ny)).contains(&d.opposite())
+                })
+            });
+            cells.push(joined.map(|pair| pair.to_vec()).unwrap_or_default());
+        }
+        resolved.push(cells);
+    }
+    let connects = |x: i64, y: i64, d: Orientation| {
+        let (nx, ny) = d.neighbour(x, y);
+        nx >= 0
+            && ny >= 0
+            && resolved
+                .get(ny as usize)
+                .and_then(|r| r.get(nx as usize))
+                .is_some_and(|e| e.contains(&d.opposite()))
+    };
+
+    let mut diagnostics = vec![];
+    for (y, row) in map.iter().enumerate() {
+        for (x, &c) in row.iter().enumerate() {
+            let point = Point { x, y };
+            let (x, y) = (x as i64, y as i64);
+            let mut report = |problem| {
+                diagnostics.push(Diagnostic {
+                    point: point.clone(),
+                    problem,
+                })
+            };
+            let dangling: Vec<Orientation> = resolved[y as usize][x as usize]
+                .iter()
+                .cloned()
+                .filter(|&d| !connects(x, y, d))
+                .collect();
+            match c {
+                ' ' => {}
+                '/' | '\\' if resolved[y as usize][x as usize].is_empty() => {
+                    report(Problem::OpenCurve)
+                }
+                '-' | '|' | '+' | '/' | '\\' => {
+                    for d in dangling {
+                        report(Problem::Dangling(d));
+                    }
+                }
+                '<' | '>' | '^' | 'v' => {
+                    let joined: Vec<Orientation> = DIRECTIONS
+                        .iter()
+                        .cloned()
+                        .filter(|&d| connects(x, y, d))
+                        .collect();
+                    let straight = exits(c)[0].is_horizontal();
+                    let across = joined.iter().any(|d| d.is_horizontal() != straight);
+                    if joined.len() == 4 {
+                        report(Problem::CartOnIntersection);
+                    } else if across && !dangling.is_empty() {
+                        report(Problem::CartOnCurve);
+                    } else {
+                        for d in dangling {
+                            report(Problem::Dangling(d));
+                        }
+                    }
+                }
+                _ => report(Problem::UnknownCharacter(c)),
+            }
+        }
+    }
+    diagnostics
+}
+
 #[derive(Debug, Clone)]
 enum Grid {
     NA,
@@ -163,29 +533,48 @@ impl Grid {
     }
 }
 
-#[derive(Debug, Clone)]
-enum Orientation {
+/// Direction in which a cart moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Orientation {
     Left,
     Right,
     Up,
     Down,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum Decision {
     Left,
     Straight,
     Right,
 }
 
+/// A cart on the track.
 #[derive(Debug, Clone)]
-struct Cart {
+pub struct Cart {
+    id: usize,
     point: Point,
     orientation: Orientation,
     decisions: VecDeque<Decision>,
 }
 
 impl Cart {
+    /// Index of the cart in reading order on the initial map.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn position(&self) -> Point {
+        Point {
+            x: self.point.x - 1,
+            y: self.point.y - 1,
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
     fn try_new(ch: char, x: usize, y: usize) -> Option<Cart> {
         let point = Point { x: x + 1, y: y + 1 };
         let mut decisions = VecDeque::new();
@@ -194,21 +583,25 @@ impl Cart {
         decisions.push_back(Decision::Right);
         match ch {
             '>' => Some(Cart {
+                id: 0,
                 point,
                 orientation: Orientation::Right,
                 decisions,
             }),
             '<' => Some(Cart {
+                id: 0,
                 point,
                 orientation: Orientation::Left,
                 decisions,
             }),
             '^' => Some(Cart {
+                id: 0,
                 point,
                 orientation: Orientation::Up,
                 decisions,
             }),
             'v' => Some(Cart {
+                id: 0,
                 point,
                 orientation: Orientation::Down,
                 decisions,
@@ -298,29 +691,6 @@ impl Cart {
     }
 }
 
-fn duplicate(points: &[Point]) -> Option<Point> {
-    let mut set: HashSet<Point> = HashSet::new();
-    for p in points {
-        if set.contains(p) {
-            return Some(p.clone());
-        }
-        set.insert(p.clone());
-    }
-    None
-}
-
-fn duplicates(points: &[Point]) -> Vec<Point> {
-    let mut set: HashSet<Point> = HashSet::new();
-    let mut result = Vec::new();
-    for p in points {
-        if set.contains(p) {
-            result.push(p.clone());
-        }
-        set.insert(p.clone());
-    }
-    result
-}
-
 #[allow(dead_code)]
 fn print_state(grid: &[Vec<Grid>], carts: &[Cart]) {
     let mut map: Vec<Vec<char>> = grid
@@ -361,7 +731,8 @@ fn test_first_crash() {
 \\-+-/  \\-+--/
   \\------/   
 ",
-    );
+    )
+    .unwrap();
     assert_eq!(p.x, 7);
     assert_eq!(p.y, 3);
 }
@@ -377,23 +748,156 @@ fn test_last_cart_location() {
   |   ^
   \\<->/
 ",
-    );
+    )
+    .unwrap();
     assert_eq!(p.x, 6);
     assert_eq!(p.y, 4);
 }
 
 #[test]
 fn test_last_cart_location_corner() {
-    let p = last_cart_location("/>>->\\");
+    let p = last_cart_location("/>>->\\\n|    |\n\\----/").unwrap();
     assert_eq!(p.x, 5);
     assert_eq!(p.y, 0);
-    let p = last_cart_location("/><->\\");
+    let p = last_cart_location("/><->\\\n|    |\n\\----/").unwrap();
     assert_eq!(p.x, 5);
     assert_eq!(p.y, 0);
-    let p = last_cart_location("/->>>\\");
+    let p = last_cart_location("/->>>\\\n|    |\n\\----/").unwrap();
     assert_eq!(p.x, 5);
     assert_eq!(p.y, 0);
-    let p = last_cart_location("/->><\\");
+    let p = last_cart_location("/->><\\\n|    |\n\\----/").unwrap();
     assert_eq!(p.x, 3);
     assert_eq!(p.y, 0);
 }
+
+#[test]
+fn test_last_cart_location_single_cart() {
+    let p = last_cart_location("/>-\\\n|  |\n\\--/").unwrap();
+    assert_eq!(p.x, 2);
+    assert_eq!(p.y, 0);
+}
+
+#[test]
+fn test_crashes() {
+    let crashes = crashes(
+        "/>-<\\  
+|   |  
+| /<+-\\
+| | | v
+\\>+</ |
+  |   ^
+  \\<->/
+",
+    )
+    .unwrap();
+    let timeline: Vec<(usize, usize, usize)> = crashes
+        .iter()
+        .map(|c| (c.tick, c.point.x, c.point.y))
+        .collect();
+    assert_eq!(vec![(1, 2, 0), (1, 2, 4), (1, 6, 4), (3, 2, 4)], timeline);
+    assert_eq!(vec![7, 2], crashes[3].carts);
+}
+
+#[test]
+fn test_validate() {
+    let problems: Vec<(usize, usize, Problem)> = validate(
+        "/>-\\
+| x|
+^--/
+/-+-\\
+|  ||
+\\-</ 
+v
+",
+    )
+    .into_iter()
+    .map(|d| (d.point.x, d.point.y, d.problem))
+    .collect();
+    assert_eq!(
+        vec![
+            (2, 1, Problem::UnknownCharacter('x')),
+            (0, 2, Problem::CartOnCurve),
+            (1, 2, Problem::Dangling(Orientation::Left)),
+            (2, 3, Problem::Dangling(Orientation::Up)),
+            (2, 3, Problem::Dangling(Orientation::Down)),
+            (3, 4, Problem::Dangling(Orientation::Up)),
+            (4, 4, Problem::Dangling(Orientation::Down)),
+            (0, 6, Problem::Dangling(Orientation::Up)),
+            (0, 6, Problem::Dangling(Orientation::Down)),
+        ],
+        problems
+    );
+    assert!(CartSystem::new("/<\\\n|+/").is_err());
+    assert!(CartSystem::new("/<\\\n\\-/").is_ok());
+    assert!(CartSystem::new("->").is_err());
+}
+
+#[test]
+fn test_no_crash() {
+    // Two carts chasing each other around a loop, which have swapped places
+    // after half of it.
+    let chase = "/>--\\
+|   |
+\\--</";
+    assert_eq!(
+        Err(SimulationError::NoCrash { period: 6 }),
+        last_cart_location(chase)
+    );
+    assert_eq!(Ok(vec![]), crashes(chase));
+
+    // The cart only returns to its initial state after taking every turn
+    // at the intersection.
+    let figure_eight = "/->-\\
+|   |
+|   |
+\\---+---\\
+    |   |
+    |   |
+    \\---/";
+    assert_eq!(
+        Err(SimulationError::NoCrash { period: 84 }),
+        first_crash(figure_eight)
+    );
+
+    assert_eq!(
+        Err(SimulationError::NoCartLeft),
+        last_cart_location("/><\\\n|  |\n\\--/")
+    );
+}
+
+#[test]
+fn test_invalid_track() {
+    match first_crash("->-") {
+        Err(SimulationError::InvalidTrack(diagnostics)) => assert_eq!(
+            vec![
+                Diagnostic {
+                    point: Point { x: 0, y: 0 },
+                    problem: Problem::Dangling(Orientation::Left),
+                },
+                Diagnostic {
+                    point: Point { x: 2, y: 0 },
+                    problem: Problem::Dangling(Orientation::Right),
+                },
+            ],
+            diagnostics
+        ),
+        other => panic!("expected an invalid track, got {:?}", other),
+    }
+    assert!(last_cart_location("->-").is_err());
+    assert!(crashes("->-").is_err());
+}
+
+#[test]
+fn test_period_of_separate_loops() {
+    // The carts go round loops of 8 and 10 cells.
+    let mut system = CartSystem::new(
+        "/>\\ /<-\\
+| | |  |
+\\-/ \\--/",
+    )
+    .unwrap();
+    while system.period().is_none() {
+        assert!(system.step().is_empty());
+    }
+    assert_eq!(Some(40), system.period());
+}
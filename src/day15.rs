@@ -1,7 +1,8 @@
-use pathfinding::prelude::{astar_bag, bfs};
-use std::cell::RefCell;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::VecDeque;
 use std::fmt;
-use std::rc::Rc;
+use std::ops::RangeInclusive;
 
 /// Determine the combat outcome given the initial position.
 ///
@@ -118,84 +119,488 @@ pub fn combat_outcome(input: &str) -> usize {
 /// "));
 /// ```
 pub fn combat_outcome2(input: &str) -> usize {
-    let mut last_fail = 4;
-    let mut max = 100;
-    let mut current = last_fail;
-    let mut count = 0;
-    loop {
-        count += 1;
-        if count > 100 {
-            return 0;
-        }
-        let mut game = Game::new(input, current);
-        while game.next_round() {}
-        if game
+    match search_elf_power(input, Search::Bisection, 4..=200).minimal_flawless() {
+        Some(trial) => trial.outcome,
+        None => 0,
+    }
+}
+
+/// The result of a combat with elves of a certain attack power.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trial {
+    pub power: usize,
+    /// Whether all elves survived.
+    pub flawless: bool,
+    pub elves_died: usize,
+    pub rounds: usize,
+    pub outcome: usize,
+}
+
+impl Trial {
+    fn run(input: &str, power: usize) -> Self {
+        let mut game = Game::new(input, power);
+        let outcome = game.fight_with(|_| {});
+        let elves_died = game
             .units
             .iter()
-            .filter(|u| u.borrow().breed == "Elf" && u.borrow().hit_point() == 0)
-            .count()
-            == 0
-        {
-            if current <= (last_fail + 1) {
-                return game.outcome();
-            } else {
-                max = current;
+            .filter(|u| game.factions[u.faction].glyph == 'E' && u.hit_point() == 0)
+            .count();
+        Trial {
+            power,
+            flawless: elves_died == 0,
+            elves_died,
+            rounds: game.rounds(),
+            outcome,
+        }
+    }
+}
+
+/// How `search_elf_power` picks the powers to try.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Search {
+    /// Try every power of the range.
+    Linear,
+    /// Bisect the range for the smallest power at which no elf dies, which
+    /// assumes that more power never hurts the elves. As a check of that
+    /// assumption the number of elves that died must not grow with the power
+    /// among the trials; if it does, every power below the result is tried
+    /// as well.
+    Bisection,
+}
+
+/// The trials of a search for the elf attack power, ordered by power.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PowerCurve {
+    pub trials: Vec<Trial>,
+}
+
+impl PowerCurve {
+    /// The trial with the smallest power at which no elf died.
+    pub fn minimal_flawless(&self) -> Option<&Trial> {
+        self.trials.iter().find(|t| t.flawless)
+    }
+
+    fn power(&self, power: usize) -> Option<&Trial> {
+        self.trials.iter().find(|t| t.power == power)
+    }
+
+    fn try_power(&mut self, input: &str, power: usize) -> bool {
+        if let Some(trial) = self.power(power) {
+            return trial.flawless;
+        }
+        let trial = Trial::run(input, power);
+        let flawless = trial.flawless;
+        let at = self.trials.iter().take_while(|t| t.power < power).count();
+        self.trials.insert(at, trial);
+        flawless
+    }
+}
+
+impl fmt::Display for PowerCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "power,flawless,elves_died,rounds,outcome")?;
+        for t in &self.trials {
+            writeln!(
+                f,
+                "{},{},{},{},{}",
+                t.power, t.flawless, t.elves_died, t.rounds, t.outcome
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Fight the combat with elves of the powers of the range, and report how
+/// it went.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day15::{search_elf_power, Search};
+///
+/// let input = "#######
+/// #.G...#
+/// #...EG#
+/// #.#.#G#
+/// #..G#E#
+/// #.....#
+/// #######
+/// ";
+/// let curve = search_elf_power(input, Search::Linear, 3..=20);
+/// assert_eq!(18, curve.trials.len());
+/// let best = curve.minimal_flawless().unwrap();
+/// assert_eq!((15, 29, 4988), (best.power, best.rounds, best.outcome));
+/// assert_eq!(1, curve.trials[14 - 3].elves_died);
+///
+/// let curve = search_elf_power(input, Search::Bisection, 3..=20);
+/// assert!(curve.trials.len() < 18);
+/// assert_eq!(Some(best), curve.minimal_flawless());
+/// ```
+pub fn search_elf_power(input: &str, search: Search, powers: RangeInclusive<usize>) -> PowerCurve {
+    let mut curve = PowerCurve::default();
+    let (mut lo, mut hi) = (*powers.start(), *powers.end());
+    match search {
+        Search::Linear => {
+            for power in powers {
+                curve.try_power(input, power);
             }
-            let revised = last_fail + (max - last_fail) / 2;
-            if current == revised {
-                current = revised - 1;
-            } else {
-                current = revised;
+        }
+        Search::Bisection => {
+            if lo > hi || !curve.try_power(input, hi) {
+                return curve;
             }
-        } else {
-            last_fail = current;
-            let revised = last_fail + (max - last_fail) / 2;
-            if current == revised {
-                current = revised + 1;
-            } else {
-                current = revised;
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if curve.try_power(input, mid) {
+                    hi = mid;
+                } else {
+                    lo = mid + 1;
+                }
+            }
+            let monotonic = curve
+                .trials
+                .windows(2)
+                .all(|w| w[0].elves_died >= w[1].elves_died);
+            if !monotonic {
+                for power in *powers.start()..hi {
+                    curve.try_power(input, power);
+                }
+            }
+        }
+    }
+    curve
+}
+
+/// Play the combat and return everything that happened, one event per line
+/// in the JSON lines format.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day15::combat_log;
+///
+/// let log = combat_log("#####
+/// #E.G#
+/// #####
+/// ", 200);
+/// assert_eq!(r#"{"event":"moved","unit":0,"from":[1,1],"to":[2,1]}
+/// {"event":"attacked","attacker":0,"target":1,"damage":200,"hp":0}
+/// {"event":"died","unit":1,"at":[3,1]}
+/// {"event":"round_completed","round":1}
+/// {"event":"combat_ended","rounds":1,"outcome":200,"winner":"Elf"}
+/// "#, log);
+/// ```
+pub fn combat_log(input: &str, elf_power: usize) -> String {
+    let mut log = String::new();
+    Game::new(input, elf_power).fight_with(|events| {
+        for e in events {
+            log += &e.to_json();
+            log.push('\n');
+        }
+    });
+    log
+}
+
+/// Settings for `generate_cave`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaveOptions {
+    /// Width of the map including the walls around it.
+    pub width: usize,
+    /// Height of the map including the walls around it.
+    pub height: usize,
+    /// Chance of a cell inside the border to be a wall.
+    pub wall_density: f64,
+    pub elves: usize,
+    pub goblins: usize,
+}
+
+impl Default for CaveOptions {
+    /// A 32x32 map where about 30% of the cells inside the border are walls,
+    /// with 10 elves and 20 goblins.
+    fn default() -> Self {
+        CaveOptions {
+            width: 32,
+            height: 32,
+            wall_density: 0.3,
+            elves: 10,
+            goblins: 20,
+        }
+    }
+}
+
+/// Generate a random cave surrounded by walls. Open caverns that are cut off
+/// from the largest open area are walled up, so that every unit can reach
+/// every other and the combat always ends. Returns `None` if the units do not
+/// fit into that area.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day15::{combat_outcome, generate_cave, CaveOptions};
+/// use rand::rngs::StdRng;
+/// use rand::SeedableRng;
+///
+/// let mut rng = StdRng::seed_from_u64(15);
+/// let options = CaveOptions {
+///     width: 12,
+///     height: 8,
+///     elves: 2,
+///     goblins: 3,
+///     ..CaveOptions::default()
+/// };
+/// let cave = generate_cave(&options, &mut rng).unwrap();
+/// assert_eq!("############\n", &cave[..13]);
+/// assert_eq!(2, cave.matches('E').count());
+/// combat_outcome(&cave);
+/// ```
+pub fn generate_cave<R: Rng>(options: &CaveOptions, rng: &mut R) -> Option<String> {
+    let (width, height) = (options.width, options.height);
+    if width < 3 || height < 3 {
+        return None;
+    }
+    let mut map: Vec<Vec<char>> = (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let border = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                    if border || rng.gen_bool(options.wall_density) {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    // Find the largest area of open caverns and wall up the others.
+    let mut area = vec![vec![usize::MAX; width]; height];
+    let mut areas: Vec<Vec<Point>> = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            if map[y][x] != '.' || area[y][x] != usize::MAX {
+                continue;
             }
+            let mut points = vec![Point(x, y)];
+            area[y][x] = areas.len();
+            let mut i = 0;
+            while i < points.len() {
+                let Point(x, y) = points[i];
+                for p in &[
+                    Point(x, y - 1),
+                    Point(x - 1, y),
+                    Point(x + 1, y),
+                    Point(x, y + 1),
+                ] {
+                    if map[p.1][p.0] == '.' && area[p.1][p.0] == usize::MAX {
+                        area[p.1][p.0] = areas.len();
+                        points.push(p.clone());
+                    }
+                }
+                i += 1;
+            }
+            areas.push(points);
         }
     }
+    let largest = (0..areas.len()).max_by_key(|&i| areas[i].len());
+    for (i, points) in areas.iter().enumerate() {
+        if Some(i) != largest {
+            for p in points {
+                map[p.1][p.0] = '#';
+            }
+        }
+    }
+
+    let mut open = match largest {
+        Some(i) => areas.swap_remove(i),
+        None => vec![],
+    };
+    if open.len() < options.elves + options.goblins {
+        return None;
+    }
+    open.shuffle(rng);
+    for (k, p) in open
+        .iter()
+        .take(options.elves + options.goblins)
+        .enumerate()
+    {
+        map[p.1][p.0] = if k < options.elves { 'E' } else { 'G' };
+    }
+    Some(
+        map.into_iter()
+            .map(|row| row.into_iter().collect::<String>() + "\n")
+            .collect(),
+    )
 }
 
+/// A position in the cave, as column and row.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-struct Point(usize, usize);
+pub struct Point(pub usize, pub usize);
 
 impl Point {
-    fn dist(&self, other: &Self) -> i64 {
-        (self.0 as i64 - other.0 as i64).abs() + (self.1 as i64 - other.1 as i64).abs()
+    /// The four neighbours of the point in reading order.
+    pub fn neighbours(&self) -> Vec<Point> {
+        vec![
+            Point(self.0, self.1 - 1),
+            Point(self.0 - 1, self.1),
+            Point(self.0 + 1, self.1),
+            Point(self.0, self.1 + 1),
+        ]
+    }
+}
+
+/// Something that happened during the combat. Units are identified by their
+/// index in reading order on the initial map.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A unit moved one step.
+    Moved { unit: usize, from: Point, to: Point },
+    /// A unit attacked another, which lost `damage` hit points and has `hp`
+    /// left.
+    Attacked {
+        attacker: usize,
+        target: usize,
+        damage: usize,
+        hp: usize,
+    },
+    /// A unit died at the given position.
+    Died { unit: usize, at: Point },
+    /// The round finished with every unit having taken its turn.
+    RoundCompleted { round: usize },
+    /// A unit found no enemies left, after the given number of full rounds.
+    CombatEnded {
+        rounds: usize,
+        outcome: usize,
+        winner: String,
+    },
+}
+
+impl Event {
+    /// Render the event as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        match self {
+            Event::Moved { unit, from, to } => format!(
+                r#"{{"event":"moved","unit":{},"from":[{},{}],"to":[{},{}]}}"#,
+                unit, from.0, from.1, to.0, to.1
+            ),
+            Event::Attacked {
+                attacker,
+                target,
+                damage,
+                hp,
+            } => format!(
+                r#"{{"event":"attacked","attacker":{},"target":{},"damage":{},"hp":{}}}"#,
+                attacker, target, damage, hp
+            ),
+            Event::Died { unit, at } => format!(
+                r#"{{"event":"died","unit":{},"at":[{},{}]}}"#,
+                unit, at.0, at.1
+            ),
+            Event::RoundCompleted { round } => {
+                format!(r#"{{"event":"round_completed","round":{}}}"#, round)
+            }
+            Event::CombatEnded {
+                rounds,
+                outcome,
+                winner,
+            } => format!(
+                r#"{{"event":"combat_ended","rounds":{},"outcome":{},"winner":{}}}"#,
+                rounds,
+                outcome,
+                json_string(winner)
+            ),
+        }
     }
 }
 
-struct Unit {
+/// A kind of unit fighting in the cave.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day15::{Faction, Game};
+///
+/// let mut orcs = Faction::new('O', "Orc");
+/// orcs.hit_points = 300;
+/// orcs.attack_power = 200;
+/// orcs.enemies = vec!['E', 'G'];
+/// let mut factions = aoc18::day15::puzzle_factions(3);
+/// for f in factions.iter_mut() {
+///     f.enemies.push('O');
+/// }
+/// factions.push(orcs);
+/// let mut game = Game::with_factions("#######
+/// #E.O.G#
+/// #######
+/// ", &factions);
+/// while game.next_round() {}
+/// assert_eq!((2, 2 * 294), (game.rounds(), game.outcome()));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Faction {
+    /// Character of the units of the faction on the map.
+    pub glyph: char,
+    pub name: String,
+    /// Hit points of every unit when the combat starts.
+    pub hit_points: usize,
+    pub attack_power: usize,
+    /// Glyphs of the factions whose units this faction attacks. The combat
+    /// ends when a unit finds none of them left.
+    pub enemies: Vec<char>,
+}
+
+impl Faction {
+    /// Create a faction with the stats of the puzzle, 200 hit points and an
+    /// attack power of 3, that has no enemies.
+    pub fn new(glyph: char, name: &str) -> Self {
+        Faction {
+            glyph,
+            name: String::from(name),
+            hit_points: 200,
+            attack_power: 3,
+            enemies: vec![],
+        }
+    }
+}
+
+/// The elves and goblins of the puzzle, hostile to each other, with elves of
+/// the given attack power.
+pub fn puzzle_factions(elf_power: usize) -> Vec<Faction> {
+    let mut elves = Faction::new('E', "Elf");
+    elves.attack_power = elf_power;
+    elves.enemies = vec!['G'];
+    let mut goblins = Faction::new('G', "Goblin");
+    goblins.enemies = vec!['E'];
+    vec![elves, goblins]
+}
+
+/// A unit fighting in the cave.
+pub struct Unit {
+    id: usize,
     hit_point: usize,
-    breed: String,
+    /// Index of the faction in the table of the game.
+    faction: usize,
     point: Point,
     power: usize,
 }
 
 impl Unit {
-    fn init(ch: char, x: usize, y: usize, epower: usize) -> Option<Rc<RefCell<Unit>>> {
-        let point = Point(x, y);
-        match ch {
-            'E' => Some(Rc::new(RefCell::new(Unit {
-                hit_point: 200,
-                breed: String::from("Elf"),
-                point,
-                power: epower,
-            }))),
-            'G' => Some(Rc::new(RefCell::new(Unit {
-                hit_point: 200,
-                breed: String::from("Goblin"),
-                point,
-                power: 3,
-            }))),
-            _ => None,
-        }
+    /// Index of the unit in reading order on the initial map.
+    pub fn id(&self) -> usize {
+        self.id
     }
 
-    fn hit_point(&self) -> usize {
+    /// Index of the faction of the unit in `Game::factions`.
+    pub fn faction(&self) -> usize {
+        self.faction
+    }
+
+    pub fn position(&self) -> &Point {
+        &self.point
+    }
+
+    /// Remaining hit points, 0 once the unit died.
+    pub fn hit_point(&self) -> usize {
         self.hit_point
     }
 
@@ -208,272 +613,742 @@ impl Unit {
     }
 }
 
-fn cmp_point(a: &Rc<RefCell<Unit>>, b: &Rc<RefCell<Unit>>) -> std::cmp::Ordering {
-    let x = a.borrow().point.1 * 10_000 + a.borrow().point.0;
-    let y = b.borrow().point.1 * 10_000 + b.borrow().point.0;
-    x.cmp(&y)
+/// Decides how the units of a faction fight. Both decisions default to the
+/// rules of the puzzle.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day15::{Game, StayStill};
+///
+/// let cave = "#######
+/// #E...G#
+/// #######
+/// ";
+/// let mut game = Game::new(cave, 3).with_strategy('E', StayStill);
+/// game.round();
+/// assert!(game.to_string().contains("#E..G.#"));
+/// ```
+pub trait Strategy {
+    /// The open cavern next to the unit that it moves to at the start of its
+    /// turn, or `None` to stay where it is. By default units that are not in
+    /// range of an enemy take `Game::next_step`.
+    fn movement(&self, game: &Game, unit: &Unit) -> Option<Point> {
+        if game.enemies_in_range(unit).is_empty() {
+            game.next_step(unit)
+        } else {
+            None
+        }
+    }
+
+    /// The id of the enemy next to the unit that it attacks after moving, or
+    /// `None` not to attack. By default that is the enemy with the fewest hit
+    /// points, first in reading order on ties.
+    fn target(&self, game: &Game, unit: &Unit) -> Option<usize> {
+        game.enemies_in_range(unit)
+            .into_iter()
+            .min_by_key(|v| v.hit_point())
+            .map(Unit::id)
+    }
 }
 
-#[derive(Clone)]
-enum Cell {
-    Wall,
-    OpenCavern,
-    Occupied,
+/// The rules of the puzzle.
+pub struct PuzzleRules;
+
+impl Strategy for PuzzleRules {}
+
+/// Attack the enemy next to the most units of the own faction, so that they
+/// can bring it down together. Ties are broken like in the puzzle.
+pub struct FocusFire;
+
+impl Strategy for FocusFire {
+    fn target(&self, game: &Game, unit: &Unit) -> Option<usize> {
+        let allies = |v: &Unit| {
+            v.position()
+                .neighbours()
+                .iter()
+                .filter(|p| matches!(game.unit_at(p), Some(a) if a.faction() == unit.faction()))
+                .count()
+        };
+        game.enemies_in_range(unit)
+            .into_iter()
+            .min_by_key(|v| (std::cmp::Reverse(allies(v)), v.hit_point()))
+            .map(Unit::id)
+    }
 }
 
-impl Cell {
-    fn new(ch: char) -> Self {
-        match ch {
-            '#' => Cell::Wall,
-            '.' => Cell::OpenCavern,
-            'E' | 'G' => Cell::Occupied,
-            _ => panic!("unknown cell type: {}", ch),
+/// Units with fewer hit points than the threshold no longer approach enemies,
+/// and step out of their range if they can. Otherwise they follow the rules
+/// of the puzzle.
+pub struct Retreat {
+    pub below: usize,
+}
+
+impl Strategy for Retreat {
+    fn movement(&self, game: &Game, unit: &Unit) -> Option<Point> {
+        if unit.hit_point() >= self.below {
+            return PuzzleRules.movement(game, unit);
+        }
+        if game.enemies_in_range(unit).is_empty() {
+            return None;
+        }
+        game.open_adjacencies(unit.position())
+            .into_iter()
+            .find(|p| {
+                p.neighbours()
+                    .iter()
+                    .all(|q| !matches!(game.unit_at(q), Some(v) if game.is_enemy(unit, v)))
+            })
+    }
+}
+
+/// Never move, only attack enemies that come in range.
+pub struct StayStill;
+
+impl Strategy for StayStill {
+    fn movement(&self, _: &Game, _: &Unit) -> Option<Point> {
+        None
+    }
+}
+
+/// Sort key of a position in reading order.
+fn reading_order(p: &Point) -> (usize, usize) {
+    (p.1, p.0)
+}
+
+/// Quote a string for JSON, escaping quotes, backslashes and control
+/// characters.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if c.is_control() => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
         }
     }
+    quoted.push('"');
+    quoted
 }
 
-struct Game {
+/// Parse hit points like `G(200)` of a unit with the given glyph.
+fn parse_hit_points(note: &str, glyph: char) -> usize {
+    let hp = note
+        .strip_prefix(glyph)
+        .and_then(|n| n.strip_prefix('('))
+        .and_then(|n| n.strip_suffix(')'))
+        .and_then(|n| n.parse().ok());
+    match hp {
+        Some(hp) => hp,
+        None => panic!("invalid hit points for {}: {}", glyph, note),
+    }
+}
+
+#[derive(Clone)]
+enum Cell {
+    Wall,
+    OpenCavern,
+    /// Occupied by the unit with the given id.
+    Occupied(usize),
+}
+
+/// State of the combat between the factions.
+pub struct Game {
+    /// The map, which also tells which unit is where.
     cells: Vec<Vec<Cell>>,
-    units: Vec<Rc<RefCell<Unit>>>,
+    /// All units of the combat including the dead ones, indexed by id.
+    units: Vec<Unit>,
+    factions: Vec<Faction>,
+    /// Whether units of the first faction attack units of the second.
+    hostile: Vec<Vec<bool>>,
+    /// Number of units alive per faction.
+    alive: Vec<usize>,
+    /// How the units of every faction fight.
+    strategies: Vec<Box<dyn Strategy>>,
     rounds: usize,
 }
 
 impl fmt::Display for Game {
+    /// Render the state as in the puzzle: the number of rounds, then the map
+    /// with the hit points of the units of every row next to it.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let s: String = self
-            .cells
+        match self.rounds {
+            0 => writeln!(f, "Initially:")?,
+            1 => writeln!(f, "After 1 round:")?,
+            n => writeln!(f, "After {} rounds:", n)?,
+        }
+        for row in &self.cells {
+            let mut line = String::new();
+            let mut notes = vec![];
+            for c in row {
+                match c {
+                    Cell::Wall => line.push('#'),
+                    Cell::OpenCavern => line.push('.'),
+                    Cell::Occupied(id) => {
+                        let u = &self.units[*id];
+                        let glyph = self.factions[u.faction].glyph;
+                        line.push(glyph);
+                        notes.push(format!("{}({})", glyph, u.hit_point()));
+                    }
+                }
+            }
+            if !notes.is_empty() {
+                line += "   ";
+                line += &notes.join(", ");
+            }
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Game {
+    /// Create the game from the map, with elves of the given attack power.
+    pub fn new(s: &str, epower: usize) -> Self {
+        Game::with_factions(s, &puzzle_factions(epower))
+    }
+
+    /// Create the game from the map, where units are drawn with the glyphs of
+    /// the factions.
+    ///
+    /// Snapshots in the format of the puzzle, as rendered by `Display`, are
+    /// read as well: the map may be preceded by a line like `After 23
+    /// rounds:`, and a row may be followed by the hit points of its units
+    /// like `G(200), E(131)`. Units without hit points start with those of
+    /// their faction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use aoc18::day15::Game;
+    ///
+    /// let snapshot = "After 2 rounds:
+    /// #######
+    /// #...G.#   G(200)
+    /// #..GEG#   G(200), E(188), G(194)
+    /// #.#.#G#   G(194)
+    /// #...#E#   E(194)
+    /// #.....#
+    /// #######
+    /// ";
+    /// let mut game = Game::new(snapshot, 3);
+    /// assert_eq!(snapshot, game.to_string());
+    /// while game.next_round() {}
+    /// assert_eq!(27730, game.outcome());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map contains a character other than a wall, an open
+    /// cavern or the glyph of a faction, or if the hit points of a row do
+    /// not match its units.
+    pub fn with_factions(s: &str, factions: &[Faction]) -> Self {
+        let mut units = vec![];
+        let mut alive = vec![0; factions.len()];
+        let mut cells = vec![];
+        let mut rounds = 0;
+        for l in s.trim().split('\n').map(str::trim) {
+            if l.ends_with(':') {
+                // "Initially:" or "After 23 rounds:"
+                rounds = match l.split_whitespace().nth(1) {
+                    Some(n) => n.parse().unwrap_or(0),
+                    None => 0,
+                };
+                continue;
+            }
+            let (map, notes) = match l.find(char::is_whitespace) {
+                Some(i) => (&l[..i], l[i..].trim()),
+                None => (l, ""),
+            };
+            let mut notes = notes.split(',').map(str::trim).filter(|n| !n.is_empty());
+            let y = cells.len();
+            let mut row = vec![];
+            for (x, c) in map.chars().enumerate() {
+                row.push(match c {
+                    '#' => Cell::Wall,
+                    '.' => Cell::OpenCavern,
+                    _ => {
+                        let i = match factions.iter().position(|f| f.glyph == c) {
+                            Some(i) => i,
+                            None => panic!("unknown cell type: {}", c),
+                        };
+                        let hit_point = match notes.next() {
+                            Some(note) => parse_hit_points(note, c),
+                            None => factions[i].hit_points,
+                        };
+                        let id = units.len();
+                        units.push(Unit {
+                            id,
+                            hit_point,
+                            faction: i,
+                            point: Point(x, y),
+                            power: factions[i].attack_power,
+                        });
+                        alive[i] += 1;
+                        Cell::Occupied(id)
+                    }
+                });
+            }
+            if let Some(note) = notes.next() {
+                panic!("no unit for hit points {} in row {}", note, y);
+            }
+            cells.push(row);
+        }
+        let hostile = factions
             .iter()
-            .map(|r| {
-                r.iter()
-                    .map(|c| match c {
-                        Cell::Wall => '#',
-                        Cell::OpenCavern => '.',
-                        Cell::Occupied => 'O',
-                    })
-                    .collect::<String>()
-                    + "\n"
+            .map(|f| {
+                factions
+                    .iter()
+                    .map(|g| f.enemies.contains(&g.glyph))
+                    .collect()
             })
             .collect();
-        let s2: String = self
-            .units
+        Game {
+            cells,
+            units,
+            factions: factions.to_vec(),
+            hostile,
+            alive,
+            strategies: factions
+                .iter()
+                .map(|_| Box::new(PuzzleRules) as Box<dyn Strategy>)
+                .collect(),
+            rounds,
+        }
+    }
+
+    /// Let the units of the faction with the glyph fight with the strategy
+    /// instead of the rules of the puzzle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no faction with the glyph.
+    pub fn with_strategy<S: Strategy + 'static>(mut self, glyph: char, strategy: S) -> Self {
+        match self.factions.iter().position(|f| f.glyph == glyph) {
+            Some(i) => self.strategies[i] = Box::new(strategy),
+            None => panic!("unknown faction: {}", glyph),
+        }
+        self
+    }
+
+    /// The factions of the combat.
+    pub fn factions(&self) -> &[Faction] {
+        &self.factions
+    }
+
+    /// All units including the dead ones, indexed by id.
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    /// Whether unit `u` attacks unit `v`, which must be alive.
+    pub fn is_enemy(&self, u: &Unit, v: &Unit) -> bool {
+        self.hostile[u.faction][v.faction] && v.hit_point() > 0
+    }
+
+    /// Whether any unit that `u` attacks is still alive.
+    fn has_enemies(&self, u: &Unit) -> bool {
+        self.hostile[u.faction]
             .iter()
-            .map(|u| u.borrow().breed.clone() + "(" + &u.borrow().hit_point().to_string() + ") ")
-            .collect();
-        write!(f, "round: {}\n{}\n{}", self.rounds, s, s2)
+            .zip(&self.alive)
+            .any(|(&hostile, &alive)| hostile && alive > 0)
     }
-}
 
-impl Game {
-    fn new(s: &str, epower: usize) -> Self {
-        Game {
-            cells: s
-                .trim()
-                .split('\n')
-                .map(|l| l.chars().map(Cell::new).collect::<Vec<_>>())
-                .collect::<Vec<_>>(),
-            units: s
-                .trim()
-                .split('\n')
-                .enumerate()
-                .map(|(i, l)| {
-                    l.chars()
-                        .enumerate()
-                        .map(|(j, c)| Unit::init(c, j, i, epower))
-                        .collect::<Vec<_>>()
-                })
-                .flatten()
-                .filter_map(|u| u)
-                .collect::<Vec<_>>(),
-            rounds: 0,
+    /// The unit standing on the point, if any.
+    pub fn unit_at(&self, p: &Point) -> Option<&Unit> {
+        match self.cells[p.1][p.0] {
+            Cell::Occupied(id) => Some(&self.units[id]),
+            _ => None,
         }
     }
 
-    fn open_adjacencies(&self, point: &Point) -> Vec<Point> {
-        vec![
-            Point(point.0 - 1, point.1),
-            Point(point.0 + 1, point.1),
-            Point(point.0, point.1 - 1),
-            Point(point.0, point.1 + 1),
-        ]
-        .into_iter()
-        .filter(|p| {
-            if let Cell::OpenCavern = self.cells[p.1][p.0] {
-                true
-            } else {
-                false
-            }
-        })
-        .collect::<Vec<_>>()
+    /// Ids of the living units in reading order of their positions.
+    fn turn_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = self
+            .units
+            .iter()
+            .filter(|u| u.hit_point() > 0)
+            .map(|u| u.id)
+            .collect();
+        order.sort_by_key(|&i| reading_order(&self.units[i].point));
+        order
     }
 
-    fn adjacencies(&self, point: &Point) -> Vec<Point> {
-        vec![
-            Point(point.0 - 1, point.1),
-            Point(point.0 + 1, point.1),
-            Point(point.0, point.1 - 1),
-            Point(point.0, point.1 + 1),
-        ]
+    /// Number of full rounds completed.
+    pub fn rounds(&self) -> usize {
+        self.rounds
     }
 
-    fn successors(&self, point: &Point) -> Vec<(Point, u32)> {
-        vec![
-            Point(point.0 - 1, point.1),
-            Point(point.0 + 1, point.1),
-            Point(point.0, point.1 - 1),
-            Point(point.0, point.1 + 1),
-        ]
-        .into_iter()
-        .filter(|p| {
-            if let Cell::OpenCavern = self.cells[p.1][p.0] {
-                true
-            } else {
-                false
-            }
-        })
-        .map(|p| (p, 1))
-        .collect::<Vec<_>>()
+    /// Play a round, returning whether it completed without the combat
+    /// ending.
+    pub fn next_round(&mut self) -> bool {
+        !matches!(self.round().last(), Some(Event::CombatEnded { .. }))
     }
 
-    fn next_round(&mut self) -> bool {
-        self.units.sort_by(cmp_point);
-        'outer: for u in self.units.iter().filter(|&x| x.borrow().hit_point() > 0) {
-            let mut has_target = false;
-            'target: for v in self
-                .units
-                .iter()
-                .filter(|&x| x.borrow().breed != u.borrow().breed && x.borrow().hit_point() > 0)
-            {
-                for adj in self.adjacencies(&u.borrow().point) {
-                    if adj == v.borrow().point {
-                        has_target = true;
-                        break 'target;
-                    }
-                }
+    /// Play the combat to the end, calling `f` with the events of every round,
+    /// and return the outcome.
+    pub fn fight_with<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(&[Event]),
+    {
+        loop {
+            let events = self.round();
+            f(&events);
+            if let Some(Event::CombatEnded { outcome, .. }) = events.last() {
+                return *outcome;
             }
+        }
+    }
 
-            if has_target {
-                let (attacked, dead_point) = self.attack_target(u);
-                if attacked {
-                    if let Some(p) = dead_point {
-                        self.cells[p.1][p.0] = Cell::OpenCavern;
-                    }
-                    continue 'outer;
+    /// The neighbours of the point that are open caverns, in reading order.
+    pub fn open_adjacencies(&self, point: &Point) -> Vec<Point> {
+        point
+            .neighbours()
+            .into_iter()
+            .filter(|p| matches!(self.cells[p.1][p.0], Cell::OpenCavern))
+            .collect::<Vec<_>>()
+    }
+
+    /// The living enemies next to the unit, in reading order.
+    pub fn enemies_in_range(&self, u: &Unit) -> Vec<&Unit> {
+        u.point
+            .neighbours()
+            .iter()
+            .filter_map(|p| self.unit_at(p))
+            .filter(|v| self.is_enemy(u, v))
+            .collect()
+    }
+
+    /// Number of steps through open caverns from the point to every cell,
+    /// `usize::MAX` where it cannot go.
+    fn distances(&self, from: &Point) -> Vec<Vec<usize>> {
+        let mut dist: Vec<Vec<usize>> = self
+            .cells
+            .iter()
+            .map(|r| vec![usize::MAX; r.len()])
+            .collect();
+        dist[from.1][from.0] = 0;
+        let mut queue = VecDeque::new();
+        queue.push_back(from.clone());
+        while let Some(p) = queue.pop_front() {
+            let d = dist[p.1][p.0] + 1;
+            for q in self.open_adjacencies(&p) {
+                if dist[q.1][q.0] == usize::MAX {
+                    dist[q.1][q.0] = d;
+                    queue.push_back(q);
                 }
             }
+        }
+        dist
+    }
 
-            let mut ideal_path = vec![];
-            let mut min_length = std::usize::MAX;
-            let mut min_weight = std::usize::MAX;
-            let enemies = self
-                .units
-                .iter()
-                .filter(|&x| x.borrow().breed != u.borrow().breed && x.borrow().hit_point() > 0);
-            if enemies.count() == 0 {
-                return false;
+    /// Play a round and return what happened. If a unit finds no enemies
+    /// left, the round stops there and ends with `Event::CombatEnded`.
+    pub fn round(&mut self) -> Vec<Event> {
+        let mut events = vec![];
+        for id in self.turn_order() {
+            let u = &self.units[id];
+            if u.hit_point() == 0 {
+                continue;
             }
-            let enemies = self
-                .units
-                .iter()
-                .filter(|&x| x.borrow().breed != u.borrow().breed && x.borrow().hit_point() > 0);
-            for v in enemies {
-                for adj in self.open_adjacencies(&v.borrow().point) {
-                    if let Some(path) = bfs(
-                        &u.borrow().point,
-                        |p| self.open_adjacencies(p),
-                        |p| *p == adj,
-                    ) {
-                        let weight = adj.1 * 10_000 + adj.0;
-                        if min_length > path.len() || (min_length == path.len() && min_weight > weight) {
-                            min_length = path.len();
-                            min_weight = weight;
-                            ideal_path = path;
-                        }
-                    }
-                }
+            if !self.has_enemies(u) {
+                events.push(Event::CombatEnded {
+                    rounds: self.rounds,
+                    outcome: self.outcome(),
+                    winner: self.factions[u.faction].name.clone(),
+                });
+                return events;
             }
 
-            if !ideal_path.is_empty() {
-                let mut min_weight = std::usize::MAX;
-                let mut new_point = Point(0, 0);
-
-                let tcell = ideal_path.last().unwrap();
-                if let Some((sol, _)) = astar_bag(
-                    &u.borrow().point,
-                    |p| self.successors(p),
-                    |p| p.dist(tcell) as u32,
-                    |p| *p == *tcell,
-                ) {
-                    for s in sol {
-                        let p = s[1].clone();
-                        let weight = p.1 * 10_000 + p.0;
-                        if min_weight > weight {
-                            min_weight = weight;
-                            new_point = p;
-                        }
-                    }
-                } else {
-                    panic!("boom")
-                }
-                {
-                    let old_point = &u.borrow().point;
-                    self.cells[old_point.1][old_point.0] = Cell::OpenCavern;
-                    self.cells[new_point.1][new_point.0] = Cell::Occupied;
-                }
-                u.borrow_mut().point = new_point.clone();
+            let strategy = &self.strategies[u.faction];
+            let step = strategy
+                .movement(self, u)
+                .filter(|p| self.open_adjacencies(&u.point).contains(p));
+            if let Some(new_point) = step {
+                let old_point = self.units[id].point.clone();
+                self.cells[old_point.1][old_point.0] = Cell::OpenCavern;
+                self.cells[new_point.1][new_point.0] = Cell::Occupied(id);
+                events.push(Event::Moved {
+                    unit: id,
+                    from: old_point,
+                    to: new_point.clone(),
+                });
+                self.units[id].point = new_point;
             }
 
-            let (attacked, dead_point) = self.attack_target(u);
-            if attacked {
-                if let Some(p) = dead_point {
-                    self.cells[p.1][p.0] = Cell::OpenCavern;
-                }
+            let u = &self.units[id];
+            let target = self.strategies[u.faction]
+                .target(self, u)
+                .filter(|&t| t < self.units.len())
+                .filter(|&t| self.enemies_in_range(u).iter().any(|v| v.id == t));
+            if let Some(target) = target {
+                events.extend(self.attack(id, target));
             }
         }
         self.rounds += 1;
-        true
-    }
-
-    fn attack_target(&self, u: &Rc<RefCell<Unit>>) -> (bool, Option<Point>) {
-        let x = u.borrow().point.0;
-        let y = u.borrow().point.1;
-        let adjacent = vec![
-            Point(x, y - 1),
-            Point(x - 1, y),
-            Point(x + 1, y),
-            Point(x, y + 1),
-        ];
-        let mut target = std::usize::MAX;
-        let mut min_hp = std::usize::MAX;
-        for adj in adjacent {
-            for (i, v) in self.units.iter().enumerate() {
-                if v.borrow().point == adj
-                    && v.borrow().breed != u.borrow().breed
-                    && v.borrow().hit_point() > 0
-                    && min_hp > v.borrow().hit_point() {
-                        min_hp = v.borrow().hit_point();
-                        target = i;
-                }
-            }
-        }
+        events.push(Event::RoundCompleted { round: self.rounds });
+        events
+    }
 
-        if target != std::usize::MAX {
-            let t = &self.units[target];
-            t.borrow_mut().take_hit(u.borrow().power);
-            if t.borrow().hit_point() == 0 {
-                let point = t.borrow().point.clone();
-                return (true, Some(point));
-            }
-            (true, None)
-        } else {
-            (false, None)
-        }
+    /// The point the unit moves to in order to get in range of an enemy, if
+    /// it can reach one. It heads for the nearest open cavern next to an
+    /// enemy, and takes the first step of a shortest path there; ties are
+    /// broken in reading order in both cases.
+    pub fn next_step(&self, u: &Unit) -> Option<Point> {
+        let dist = self.distances(&u.point);
+        let target = self
+            .units
+            .iter()
+            .filter(|v| self.is_enemy(u, v))
+            .flat_map(|v| self.open_adjacencies(&v.point))
+            .filter(|p| dist[p.1][p.0] != usize::MAX)
+            .min_by_key(|p| (dist[p.1][p.0], reading_order(p)))?;
+        let back = self.distances(&target);
+        self.open_adjacencies(&u.point)
+            .into_iter()
+            .min_by_key(|p| back[p.1][p.0])
     }
 
-    fn outcome(&self) -> usize {
-        let mut hp = 0;
-        for u in self.units.iter() {
-            hp += u.borrow().hit_point();
+    /// Let unit `id` attack unit `target`.
+    fn attack(&mut self, id: usize, target: usize) -> Vec<Event> {
+        let power = self.units[id].power;
+        let t = &mut self.units[target];
+        let hp = t.hit_point();
+        t.take_hit(power);
+        let mut events = vec![Event::Attacked {
+            attacker: id,
+            target,
+            damage: hp - t.hit_point(),
+            hp: t.hit_point(),
+        }];
+        if t.hit_point() == 0 {
+            let at = t.point.clone();
+            self.alive[t.faction] -= 1;
+            self.cells[at.1][at.0] = Cell::OpenCavern;
+            events.push(Event::Died { unit: target, at });
         }
-        self.rounds * hp
+        events
+    }
+
+    /// Number of full rounds completed multiplied by the sum of the hit
+    /// points of all remaining units.
+    pub fn outcome(&self) -> usize {
+        self.rounds * self.units.iter().map(Unit::hit_point).sum::<usize>()
+    }
+}
+
+#[test]
+fn test_round_events() {
+    let mut game = Game::new(
+        "#######
+#.G...#
+#...EG#
+#.#.#G#
+#..G#E#
+#.....#
+#######
+",
+        3,
+    );
+    let events = game.round();
+    assert_eq!(
+        Some(&Event::Moved {
+            unit: 0,
+            from: Point(2, 1),
+            to: Point(3, 1),
+        }),
+        events.first()
+    );
+    assert!(events.contains(&Event::Attacked {
+        attacker: 2,
+        target: 1,
+        damage: 3,
+        hp: 197,
+    }));
+    assert_eq!(Some(&Event::RoundCompleted { round: 1 }), events.last());
+
+    let mut deaths = 0;
+    let outcome = game.fight_with(|events| {
+        deaths += events
+            .iter()
+            .filter(|e| matches!(e, Event::Died { .. }))
+            .count()
+    });
+    assert_eq!(27730, outcome);
+    assert_eq!(47, game.rounds());
+    assert_eq!(2, deaths);
+}
+
+#[test]
+fn test_event_json() {
+    let event = Event::CombatEnded {
+        rounds: 47,
+        outcome: 27730,
+        winner: String::from("\"Sly\"\\Orc\n\u{7}"),
+    };
+    assert_eq!(
+        r#"{"event":"combat_ended","rounds":47,"outcome":27730,"winner":"\"Sly\"\\Orc\n\u0007"}"#,
+        event.to_json()
+    );
+}
+
+#[test]
+fn test_movement() {
+    let game = Game::new(
+        "#######
+#E..G.#
+#...#.#
+#.G.#G#
+#######
+",
+        3,
+    );
+    assert_eq!(Some(Point(2, 1)), game.next_step(&game.units()[0]));
+
+    let game = Game::new(
+        "#######
+#.E...#
+#.....#
+#...G.#
+#######
+",
+        3,
+    );
+    assert_eq!(Some(Point(3, 1)), game.next_step(&game.units()[0]));
+
+    let game = Game::new(
+        "#####
+#E#G#
+#####
+",
+        3,
+    );
+    assert_eq!(None, game.next_step(&game.units()[0]));
+}
+
+#[test]
+fn test_snapshot() {
+    let mut game = Game::new(
+        "#######
+#.G...#
+#...EG#
+#.#.#G#
+#..G#E#
+#.....#
+#######
+",
+        3,
+    );
+    for _ in 0..20 {
+        game.next_round();
     }
+    let mut resumed = Game::new(&game.to_string(), 3);
+    assert_eq!(game.to_string(), resumed.to_string());
+    assert_eq!(game.fight_with(|_| {}), resumed.fight_with(|_| {}));
+    assert_eq!(
+        "After 47 rounds:
+#######
+#G....#   G(200)
+#.G...#   G(131)
+#.#.#G#   G(59)
+#...#.#
+#....G#   G(200)
+#######
+",
+        resumed.to_string()
+    );
+}
+
+#[test]
+fn test_generated_caves() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    let mut rng = StdRng::seed_from_u64(46);
+    for density in &[0.0, 0.2, 0.4] {
+        let options = CaveOptions {
+            width: 16,
+            height: 12,
+            wall_density: *density,
+            elves: 4,
+            goblins: 6,
+        };
+        let cave = generate_cave(&options, &mut rng).unwrap();
+        let rows: Vec<&str> = cave.lines().collect();
+        assert_eq!(12, rows.len());
+        assert!(rows
+            .iter()
+            .all(|r| r.len() == 16 && r.starts_with('#') && r.ends_with('#')));
+        assert!(rows[0].chars().chain(rows[11].chars()).all(|c| c == '#'));
+        assert_eq!(
+            (4, 6),
+            (cave.matches('E').count(), cave.matches('G').count())
+        );
+        let mut game = Game::new(&cave, 3);
+        game.fight_with(|_| {});
+    }
+
+    let crowded = CaveOptions {
+        width: 4,
+        height: 4,
+        ..CaveOptions::default()
+    };
+    assert_eq!(None, generate_cave(&crowded, &mut rng));
+}
+
+#[test]
+fn test_strategies() {
+    let cave = "#######
+#.G...#
+#...EG#
+#.#.#G#
+#..G#E#
+#.....#
+#######
+";
+    let mut game = Game::new(cave, 3).with_strategy('E', PuzzleRules);
+    assert_eq!(27730, game.fight_with(|_| {}));
+
+    // The goblin at the top is next to two elves, which both attack it.
+    let cave = "#####
+#EGE#
+#.G.#
+#####
+";
+    let mut game = Game::new(cave, 3).with_strategy('E', FocusFire);
+    let targets: Vec<usize> = game
+        .round()
+        .iter()
+        .filter_map(|e| match e {
+            Event::Attacked {
+                attacker, target, ..
+            } if *attacker == 0 || *attacker == 2 => Some(*target),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec![1, 1], targets);
+
+    // A wounded elf steps out of range of a goblin that stays where it is.
+    let cave = "#####
+#.E.#
+#.G.#
+#####
+";
+    let mut game = Game::new(&cave.replace("#.E.#", "#.E.#   E(2)"), 3)
+        .with_strategy('E', Retreat { below: 10 })
+        .with_strategy('G', StayStill);
+    let events = game.round();
+    assert_eq!(
+        Event::Moved {
+            unit: 0,
+            from: Point(2, 1),
+            to: Point(1, 1),
+        },
+        events[0]
+    );
+    assert!(events.iter().all(|e| !matches!(e, Event::Died { .. })));
 }
@@ -1,6 +1,9 @@
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
-use std::rc::Rc;
+use crate::elfcode;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 
 /// Find the number of samples that behave like three or more opcodes.
@@ -15,147 +18,385 @@ use std::str::FromStr;
 /// After:  [3, 2, 2, 1]"));
 /// ```
 pub fn num_samples(input: &str) -> usize {
-    let parts: Vec<&str> = input.split("\n\n\n\n").collect();
-    let samples = parts[0];
-    samples
-        .split("\n\n")
-        .filter(|s| !s.trim().is_empty())
-        .map(parse_sample)
-        .filter(|(before, op, after)| {
-            let mut count = 0;
-            for i in op.enumerate_all() {
-                if before.execute(&i) == *after {
-                    count += 1;
-                }
-            }
-            count >= 3
-        })
+    parse_samples(input)
+        .iter()
+        .filter(|s| s.candidates().len() >= 3)
         .count()
 }
 
 /// Find the register 0 in the result after executing the test program.
 pub fn result_r0(input: &str) -> i64 {
-    let parts: Vec<&str> = input.split("\n\n\n\n").collect();
-    let samples = parts[0];
-    let program = parts[1];
-    // Populate a list of set of possible instructions for every OP code.
-    let mut op_table: HashMap<i64, Rc<RefCell<Vec<HashSet<Operation>>>>> = HashMap::new();
-    for sample in samples
-        .split("\n\n")
-        .filter(|s| !s.trim().is_empty())
-        .map(parse_sample)
-    {
-        let (before, op, after) = sample;
-        let mut set = HashSet::new();
-        for i in op.enumerate_all() {
-            if before.execute(&i) == after {
-                set.insert(i.op);
-            }
-        }
-        if let Operation::OP(o) = op.op {
-            op_table
-                .entry(o)
-                .or_insert_with(|| Rc::new(RefCell::new(vec![])))
-                .borrow_mut()
-                .push(set);
-        }
+    let map = deduce_opcodes(&parse_samples(input)).unwrap();
+    let program = input.split("\n\n\n\n").nth(1).unwrap();
+    elfcode::decode(program, &map)
+        .unwrap()
+        .run(Device::new(4))
+        .unwrap()
+        .registers[0]
+}
+
+/// Determine the operation of every opcode number that appears in the
+/// samples.
+///
+/// Every opcode performs a different operation, so this looks for the
+/// assignments of operations to opcodes that agree with all samples. If more
+/// than one assignment is possible, the opcodes that differ between them are
+/// reported along with the operations they could perform.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day16::{deduce_opcodes, Operation, Sample};
+/// use std::str::FromStr;
+///
+/// let sample = Sample::from_str("Before: [3, 2, 1, 1]
+/// 9 2 1 2
+/// After:  [3, 2, 2, 1]").unwrap();
+/// let ambiguity = deduce_opcodes(&[sample]).unwrap_err();
+/// assert_eq!(
+///     vec![(9, vec![Operation::ADDI, Operation::MULR, Operation::SETI])],
+///     ambiguity.candidates
+/// );
+/// ```
+pub fn deduce_opcodes(samples: &[Sample]) -> Result<OpcodeMap, Ambiguity> {
+    // Operations (as indices into `Operation::ALL`) that agree with every
+    // sample of an opcode.
+    let mut candidates: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+    for sample in samples {
+        let o = match sample.instruction.op {
+            Operation::OP(o) if o >= 0 => o,
+            _ => continue,
+        };
+        let matching: Vec<usize> = sample
+            .candidates()
+            .iter()
+            .map(|op| Operation::ALL.iter().position(|x| x == op).unwrap())
+            .collect();
+        candidates
+            .entry(o)
+            .and_modify(|c| c.retain(|op| matching.contains(op)))
+            .or_insert(matching);
     }
+    let opcodes: Vec<i64> = candidates.keys().cloned().collect();
+    let candidates: Vec<Vec<usize>> = candidates.into_values().collect();
 
-    let mut known = vec![Operation::OP(-1); 16];
-    let mut unprocessed = vec![HashSet::new(); 16];
-
-    // First find the most certain instructions for a given OP code.
-    for (o, l) in op_table.iter() {
-        let all_ops: HashSet<Operation> = [
-            Operation::ADDR,
-            Operation::ADDI,
-            Operation::MULR,
-            Operation::MULI,
-            Operation::BANR,
-            Operation::BANI,
-            Operation::BORR,
-            Operation::BORI,
-            Operation::SETR,
-            Operation::SETI,
-            Operation::GTIR,
-            Operation::GTRI,
-            Operation::GTRR,
-            Operation::EQIR,
-            Operation::EQRI,
-            Operation::EQRR,
-        ]
+    // Keep only the operations that are part of some complete assignment.
+    let feasible: Vec<Vec<usize>> = candidates
         .iter()
-        .cloned()
+        .enumerate()
+        .map(|(i, ops)| {
+            ops.iter()
+                .cloned()
+                .filter(|&op| {
+                    let forced: Vec<Vec<usize>> = candidates
+                        .iter()
+                        .enumerate()
+                        .map(|(j, c)| {
+                            if j == i {
+                                vec![op]
+                            } else {
+                                c.iter().cloned().filter(|&x| x != op).collect()
+                            }
+                        })
+                        .collect();
+                    assign(&forced).is_some()
+                })
+                .collect()
+        })
         .collect();
-        let reduced: HashSet<_> = l
-            .borrow()
-            .iter()
-            .fold(all_ops, |acc, x| acc.intersection(&x).cloned().collect());
-        if reduced.len() == 1 {
-            known[*o as usize] = reduced.iter().next().unwrap().clone();
-        } else {
-            unprocessed[*o as usize] = reduced;
+
+    if feasible.iter().all(|f| f.len() == 1) {
+        let mut operations = vec![None; opcodes.last().map_or(0, |&o| o as usize + 1)];
+        for (o, f) in opcodes.iter().zip(&feasible) {
+            operations[*o as usize] = Some(Operation::ALL[f[0]].clone());
         }
+        return Ok(OpcodeMap::new(operations));
     }
+    // Without any complete assignment the samples contradict each other, so
+    // report the candidates of every opcode.
+    let contradiction = feasible.iter().any(|f| f.is_empty());
+    let reported = if contradiction {
+        &candidates
+    } else {
+        &feasible
+    };
+    Err(Ambiguity {
+        candidates: opcodes
+            .iter()
+            .zip(reported)
+            .filter(|(_, c)| contradiction || c.len() != 1)
+            .map(|(o, c)| (*o, c.iter().map(|&op| Operation::ALL[op].clone()).collect()))
+            .collect(),
+    })
+}
 
-    loop {
-        // Eliminate redundant choices based on known choices.
-        let mut should_break = true;
-        for j in 0..16 {
-            let c = known[j].clone();
-            if c == Operation::OP(-1) {
+/// Find an operation for every opcode so that no two opcodes share one, where
+/// `candidates[i]` lists the operations allowed for opcode `i`.
+fn assign(candidates: &[Vec<usize>]) -> Option<Vec<usize>> {
+    // Augmenting paths: try to give each opcode an operation, taking it away
+    // from the opcode that holds it if that one can move to another.
+    fn augment(
+        i: usize,
+        candidates: &[Vec<usize>],
+        owner: &mut Vec<Option<usize>>,
+        seen: &mut Vec<bool>,
+    ) -> bool {
+        for &op in &candidates[i] {
+            if seen[op] {
                 continue;
             }
-            for (i, u) in unprocessed.iter_mut().enumerate() {
-                u.remove(&c);
-                if u.len() > 1 {
-                    should_break = false;
-                } else if u.len() == 1 {
-                    known[i] = u.iter().next().unwrap().clone();
-                }
+            seen[op] = true;
+            let free = match owner[op] {
+                Some(j) => augment(j, candidates, owner, seen),
+                None => true,
+            };
+            if free {
+                owner[op] = Some(i);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owner = vec![None; Operation::ALL.len()];
+    for i in 0..candidates.len() {
+        if !augment(
+            i,
+            candidates,
+            &mut owner,
+            &mut vec![false; Operation::ALL.len()],
+        ) {
+            return None;
+        }
+    }
+    let mut result = vec![0; candidates.len()];
+    for (op, i) in owner.iter().enumerate() {
+        if let Some(i) = i {
+            result[*i] = op;
+        }
+    }
+    Some(result)
+}
+
+/// Opcodes whose operation cannot be determined from the samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ambiguity {
+    /// The ambiguous opcodes with the operations they could perform. If the
+    /// samples contradict each other, every opcode is listed with the
+    /// operations that agree with its own samples.
+    pub candidates: Vec<(i64, Vec<Operation>)>,
+}
+
+impl fmt::Display for Ambiguity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (o, ops)) in self.candidates.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
             }
+            let ops: Vec<String> = ops.iter().map(|op| op.to_string()).collect();
+            write!(f, "opcode {}: {}", o, ops.join(", "))?;
         }
-        if should_break {
-            break;
+        Ok(())
+    }
+}
+
+/// The state of a device before and after executing an instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub before: Device,
+    pub instruction: Instruction,
+    pub after: Device,
+}
+
+impl Sample {
+    /// Find the operations that turn `before` into `after` with the operands
+    /// of the instruction.
+    pub fn candidates(&self) -> Vec<Operation> {
+        let i = &self.instruction;
+        Operation::ALL
+            .iter()
+            .filter(|op| {
+                let i = Instruction {
+                    op: (*op).clone(),
+                    a: i.a,
+                    b: i.b,
+                    c: i.c,
+                };
+                self.before.execute(&i).as_ref() == Ok(&self.after)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl FromStr for Sample {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split('\n').collect();
+        if parts.len() != 3 {
+            return Err(());
         }
+        Ok(Sample {
+            before: Device::from_str(parts[0].trim_start_matches("Before:").trim())?,
+            instruction: Instruction::from_str(parts[1])?,
+            after: Device::from_str(parts[2].trim_start_matches("After:").trim())?,
+        })
     }
+}
 
-    program
-        .split('\n')
+fn parse_samples(input: &str) -> Vec<Sample> {
+    input
+        .split("\n\n\n\n")
+        .next()
+        .unwrap()
+        .split("\n\n")
         .filter(|s| !s.trim().is_empty())
-        .fold(
-            Device {
-                registers: [0, 0, 0, 0],
-            },
-            |acc, s| {
-                let i = Instruction::from_str(s).unwrap();
-                if let Operation::OP(o) = i.op {
-                    let ni = Instruction {
-                        op: known[o as usize].clone(),
-                        a: i.a,
-                        b: i.b,
-                        c: i.c,
-                    };
-                    acc.execute(&ni)
-                } else {
-                    acc
-                }
-            },
-        )
-        .registers[0]
+        .map(|s| Sample::from_str(s).unwrap())
+        .collect()
+}
+
+/// Settings for `generate_input`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorOptions {
+    /// Number of samples.
+    pub samples: usize,
+    /// Number of instructions in the test program.
+    pub program_len: usize,
+    /// Number of registers of the device. `result_r0` always runs the test
+    /// program on a device with 4 registers, so it cannot solve inputs
+    /// generated with any other number.
+    pub registers: usize,
+    /// Largest register value in the samples.
+    pub max_value: i64,
+    /// How many operations every sample may behave like. Narrow ranges near 1
+    /// give samples that identify their operation on their own, wide ones
+    /// give samples like the puzzle's.
+    pub candidates: RangeInclusive<usize>,
+}
+
+impl Default for GeneratorOptions {
+    /// 800 samples and a test program of 1000 instructions for a device with
+    /// 4 registers holding values up to 3, with samples that behave like
+    /// anywhere from 1 to 16 operations.
+    fn default() -> Self {
+        GeneratorOptions {
+            samples: 800,
+            program_len: 1000,
+            registers: 4,
+            max_value: 3,
+            candidates: 1..=16,
+        }
+    }
 }
 
-fn parse_sample(input: &str) -> (Device, Instruction, Device) {
-    let parts: Vec<&str> = input.trim().split('\n').collect();
-    (
-        Device::from_str(parts[0].replace("Before: ", "").as_str()).unwrap(),
-        Instruction::from_str(parts[1]).unwrap(),
-        Device::from_str(parts[2].replace("After:  ", "").as_str()).unwrap(),
-    )
+/// Create a secret opcode map that assigns every operation a random opcode.
+pub fn random_opcode_map<R: Rng>(rng: &mut R) -> OpcodeMap {
+    let mut operations: Vec<Option<Operation>> = Operation::ALL.iter().cloned().map(Some).collect();
+    operations.shuffle(rng);
+    OpcodeMap::new(operations)
 }
 
+/// Generate a puzzle input for the opcodes of the map: samples of every mapped
+/// operation in turn, followed by a test program that runs on a device with
+/// the given number of registers. Returns `None` if the options ask for no
+/// samples, no registers or a negative largest value, or if no sample of some
+/// operation falls within the range of candidates after many attempts.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day16::{generate_input, random_opcode_map, result_r0, GeneratorOptions};
+/// use rand::rngs::StdRng;
+/// use rand::SeedableRng;
+///
+/// let mut rng = StdRng::seed_from_u64(16);
+/// let map = random_opcode_map(&mut rng);
+/// let options = GeneratorOptions {
+///     candidates: 1..=2,
+///     ..GeneratorOptions::default()
+/// };
+/// let input = generate_input(&map, &options, &mut rng).unwrap();
+/// assert!(input.starts_with("Before: ["));
+/// result_r0(&input);
+/// ```
+pub fn generate_input<R: Rng>(
+    map: &OpcodeMap,
+    options: &GeneratorOptions,
+    rng: &mut R,
+) -> Option<String> {
+    let mapped: Vec<(i64, &Operation)> = Operation::ALL
+        .iter()
+        .filter_map(|op| map.opcode(op).map(|o| (o, op)))
+        .collect();
+    if mapped.is_empty() {
+        return None;
+    }
+    if options.samples == 0 || options.registers == 0 || options.max_value < 0 {
+        return None;
+    }
+    let registers = options.registers as i64;
+
+    let mut samples = vec![];
+    for k in 0..options.samples {
+        let (opcode, op) = mapped[k % mapped.len()];
+        let sample = (0..10_000).find_map(|_| {
+            let before = Device {
+                registers: (0..registers)
+                    .map(|_| rng.gen_range(0, options.max_value + 1))
+                    .collect(),
+                word: Word::I64,
+            };
+            let instruction = Instruction {
+                op: op.clone(),
+                a: rng.gen_range(0, registers),
+                b: rng.gen_range(0, registers),
+                c: rng.gen_range(0, registers),
+            };
+            let after = before.execute(&instruction).ok()?;
+            let sample = Sample {
+                before,
+                instruction,
+                after,
+            };
+            if options.candidates.contains(&sample.candidates().len()) {
+                Some(sample)
+            } else {
+                None
+            }
+        })?;
+        samples.push(format!(
+            "Before: {:?}\n{} {} {} {}\nAfter:  {:?}\n",
+            sample.before.registers,
+            opcode,
+            sample.instruction.a,
+            sample.instruction.b,
+            sample.instruction.c,
+            sample.after.registers
+        ));
+    }
+    samples.shuffle(rng);
+
+    let program: Vec<String> = (0..options.program_len)
+        .map(|_| {
+            let (opcode, _) = mapped[rng.gen_range(0, mapped.len())];
+            format!(
+                "{} {} {} {}\n",
+                opcode,
+                rng.gen_range(0, registers),
+                rng.gen_range(0, registers),
+                rng.gen_range(0, registers)
+            )
+        })
+        .collect();
+    Some(format!("{}\n\n\n{}", samples.join("\n"), program.concat()))
+}
+
+/// Operation performed by an instruction. `OP` holds an opcode number whose
+/// operation is not known yet.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-enum Operation {
+pub enum Operation {
     ADDR,
     ADDI,
     MULR,
@@ -176,141 +417,168 @@ enum Operation {
     OP(i64),
 }
 
+impl Operation {
+    /// Every operation that the device knows.
+    pub const ALL: [Operation; 16] = [
+        Operation::ADDR,
+        Operation::ADDI,
+        Operation::MULR,
+        Operation::MULI,
+        Operation::BANR,
+        Operation::BANI,
+        Operation::BORR,
+        Operation::BORI,
+        Operation::SETR,
+        Operation::SETI,
+        Operation::GTIR,
+        Operation::GTRI,
+        Operation::GTRR,
+        Operation::EQIR,
+        Operation::EQRI,
+        Operation::EQRR,
+    ];
+
+    /// Whether the operands `a` and `b` refer to registers rather than holding
+    /// values.
+    pub fn register_operands(&self) -> (bool, bool) {
+        match self {
+            Operation::ADDR
+            | Operation::MULR
+            | Operation::BANR
+            | Operation::BORR
+            | Operation::GTRR
+            | Operation::EQRR => (true, true),
+            Operation::ADDI
+            | Operation::MULI
+            | Operation::BANI
+            | Operation::BORI
+            | Operation::GTRI
+            | Operation::EQRI
+            | Operation::SETR => (true, false),
+            Operation::GTIR | Operation::EQIR => (false, true),
+            Operation::SETI | Operation::OP(_) => (false, false),
+        }
+    }
+}
+
+impl FromStr for Operation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "addr" => Ok(Operation::ADDR),
+            "addi" => Ok(Operation::ADDI),
+            "mulr" => Ok(Operation::MULR),
+            "muli" => Ok(Operation::MULI),
+            "banr" => Ok(Operation::BANR),
+            "bani" => Ok(Operation::BANI),
+            "borr" => Ok(Operation::BORR),
+            "bori" => Ok(Operation::BORI),
+            "setr" => Ok(Operation::SETR),
+            "seti" => Ok(Operation::SETI),
+            "gtir" => Ok(Operation::GTIR),
+            "gtri" => Ok(Operation::GTRI),
+            "gtrr" => Ok(Operation::GTRR),
+            "eqir" => Ok(Operation::EQIR),
+            "eqri" => Ok(Operation::EQRI),
+            "eqrr" => Ok(Operation::EQRR),
+            o => o.parse().map(Operation::OP).map_err(|_| ()),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::ADDR => "addr",
+            Operation::ADDI => "addi",
+            Operation::MULR => "mulr",
+            Operation::MULI => "muli",
+            Operation::BANR => "banr",
+            Operation::BANI => "bani",
+            Operation::BORR => "borr",
+            Operation::BORI => "bori",
+            Operation::SETR => "setr",
+            Operation::SETI => "seti",
+            Operation::GTIR => "gtir",
+            Operation::GTRI => "gtri",
+            Operation::GTRR => "gtrr",
+            Operation::EQIR => "eqir",
+            Operation::EQRI => "eqri",
+            Operation::EQRR => "eqrr",
+            Operation::OP(o) => return write!(f, "{}", o),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Mapping from opcode numbers to the operations they perform.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpcodeMap {
+    operations: Vec<Option<Operation>>,
+}
+
+impl OpcodeMap {
+    /// Create a map where the opcode number is the index into `operations`.
+    pub fn new(operations: Vec<Option<Operation>>) -> Self {
+        OpcodeMap { operations }
+    }
+
+    /// The operation performed by the given opcode number, if known.
+    pub fn operation(&self, opcode: i64) -> Option<&Operation> {
+        if opcode < 0 {
+            return None;
+        }
+        self.operations.get(opcode as usize)?.as_ref()
+    }
+
+    /// The opcode number of the given operation, if known.
+    pub fn opcode(&self, op: &Operation) -> Option<i64> {
+        self.operations
+            .iter()
+            .position(|o| o.as_ref() == Some(op))
+            .map(|i| i as i64)
+    }
+}
+
+/// An instruction with its operation and three operands.
 #[derive(Clone, Debug, PartialEq)]
-struct Instruction {
-    op: Operation,
-    a: i64,
-    b: i64,
-    c: i64,
+pub struct Instruction {
+    pub op: Operation,
+    pub a: i64,
+    pub b: i64,
+    pub c: i64,
 }
 
 impl FromStr for Instruction {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let r: Vec<i64> = s
-            .trim()
-            .split(' ')
-            .map(|s| s.trim().parse().unwrap())
-            .collect();
+        let r: Vec<&str> = s.split_whitespace().collect();
+        if r.len() != 4 {
+            return Err(());
+        }
+        let arg = |s: &str| s.parse().map_err(|_| ());
         Ok(Instruction {
-            op: Operation::OP(r[0]),
-            a: r[1],
-            b: r[2],
-            c: r[3],
+            op: Operation::from_str(r[0])?,
+            a: arg(r[1])?,
+            b: arg(r[2])?,
+            c: arg(r[3])?,
         })
     }
 }
 
-impl Instruction {
-    fn enumerate_all(&self) -> Vec<Self> {
-        if let Operation::OP(_) = &self.op {
-            return vec![
-                Instruction {
-                    op: Operation::ADDR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::ADDI,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::MULR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::MULI,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::BANR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::BANI,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::BORR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::BORI,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::SETR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::SETI,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::GTIR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::GTRI,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::GTRR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::EQIR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::EQRI,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-                Instruction {
-                    op: Operation::EQRR,
-                    a: self.a,
-                    b: self.b,
-                    c: self.c,
-                },
-            ];
-        }
-        panic!("not a generic instruction")
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.op, self.a, self.b, self.c)
     }
 }
 
+/// State of the device's registers.
 #[derive(Clone, Debug, PartialEq)]
-struct Device {
-    registers: [i64; 4],
+pub struct Device {
+    pub registers: Vec<i64>,
+    pub word: Word,
 }
 
 impl FromStr for Device {
@@ -320,94 +588,324 @@ impl FromStr for Device {
         let r: Vec<i64> = s
             .trim_matches(|c| c == '[' || c == ']')
             .split(',')
-            .map(|s| s.trim().parse().unwrap())
-            .collect();
+            .map(|s| s.trim().parse().map_err(|_| ()))
+            .collect::<Result<_, _>>()?;
         Ok(Device {
-            registers: [r[0], r[1], r[2], r[3]],
+            registers: r,
+            word: Word::I64,
         })
     }
 }
 
 impl Device {
-    fn execute(&self, i: &Instruction) -> Self {
+    /// Create a device with the given number of registers, all set to 0.
+    pub fn new(count: usize) -> Self {
+        Device {
+            registers: vec![0; count],
+            word: Word::I64,
+        }
+    }
+
+    /// Use the given word size for register values.
+    pub fn with_word(mut self, word: Word) -> Self {
+        self.word = word;
+        self
+    }
+
+    /// Value of the given register.
+    pub fn register(&self, r: i64) -> Result<i64, DeviceError> {
+        Ok(self.registers[self.index(r)?])
+    }
+
+    /// Set the value of the given register.
+    pub fn set_register(&mut self, r: i64, value: i64) -> Result<(), DeviceError> {
+        let r = self.index(r)?;
+        self.registers[r] = self.word.wrap(value);
+        Ok(())
+    }
+
+    fn index(&self, r: i64) -> Result<usize, DeviceError> {
+        if r < 0 || r as usize >= self.registers.len() {
+            return Err(DeviceError::InvalidRegister {
+                register: r,
+                count: self.registers.len(),
+            });
+        }
+        Ok(r as usize)
+    }
+
+    /// Execute a single instruction and return the resulting state.
+    pub fn execute(&self, i: &Instruction) -> Result<Self, DeviceError> {
+        let r = |x| self.register(x);
+        let value = match i.op {
+            Operation::ADDR => r(i.a)?.wrapping_add(r(i.b)?),
+            Operation::ADDI => r(i.a)?.wrapping_add(i.b),
+            Operation::MULR => r(i.a)?.wrapping_mul(r(i.b)?),
+            Operation::MULI => r(i.a)?.wrapping_mul(i.b),
+            Operation::BANR => r(i.a)? & r(i.b)?,
+            Operation::BANI => r(i.a)? & i.b,
+            Operation::BORR => r(i.a)? | r(i.b)?,
+            Operation::BORI => r(i.a)? | i.b,
+            Operation::SETR => r(i.a)?,
+            Operation::SETI => i.a,
+            Operation::GTIR => i64::from(i.a > r(i.b)?),
+            Operation::GTRI => i64::from(r(i.a)? > i.b),
+            Operation::GTRR => i64::from(r(i.a)? > r(i.b)?),
+            Operation::EQIR => i64::from(i.a == r(i.b)?),
+            Operation::EQRI => i64::from(r(i.a)? == i.b),
+            Operation::EQRR => i64::from(r(i.a)? == r(i.b)?),
+            Operation::OP(o) => return Err(DeviceError::UnknownOperation(o)),
+        };
         let mut result = self.clone();
-        match i.op.clone() {
-            Operation::ADDR => {
-                result.registers[i.c as usize] =
-                    result.registers[i.a as usize] + result.registers[i.b as usize]
-            }
-            Operation::ADDI => {
-                result.registers[i.c as usize] = result.registers[i.a as usize] + i.b
-            }
-            Operation::MULR => {
-                result.registers[i.c as usize] =
-                    result.registers[i.a as usize] * result.registers[i.b as usize]
-            }
-            Operation::MULI => {
-                result.registers[i.c as usize] = result.registers[i.a as usize] * i.b
-            }
-            Operation::BANR => {
-                result.registers[i.c as usize] =
-                    result.registers[i.a as usize] & result.registers[i.b as usize]
-            }
-            Operation::BANI => {
-                result.registers[i.c as usize] = result.registers[i.a as usize] & i.b
-            }
-            Operation::BORR => {
-                result.registers[i.c as usize] =
-                    result.registers[i.a as usize] | result.registers[i.b as usize]
-            }
-            Operation::BORI => {
-                result.registers[i.c as usize] = result.registers[i.a as usize] | i.b
-            }
-            Operation::SETR => result.registers[i.c as usize] = result.registers[i.a as usize],
-            Operation::SETI => result.registers[i.c as usize] = i.a,
-            Operation::GTIR => {
-                result.registers[i.c as usize] = if i.a > result.registers[i.b as usize] {
-                    1
-                } else {
-                    0
-                }
-            }
-            Operation::GTRI => {
-                result.registers[i.c as usize] = if result.registers[i.a as usize] > i.b {
-                    1
-                } else {
-                    0
-                }
-            }
-            Operation::GTRR => {
-                result.registers[i.c as usize] =
-                    if result.registers[i.a as usize] > result.registers[i.b as usize] {
-                        1
-                    } else {
-                        0
-                    }
+        result.set_register(i.c, value)?;
+        Ok(result)
+    }
+}
+
+/// Word size of the register values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Word {
+    /// Signed 64-bit values.
+    I64,
+    /// Unsigned 32-bit values that wrap around on overflow.
+    WrappingU32,
+}
+
+impl Word {
+    fn wrap(self, value: i64) -> i64 {
+        match self {
+            Word::I64 => value,
+            Word::WrappingU32 => i64::from(value as u32),
+        }
+    }
+}
+
+/// Error raised by the device while executing an instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceError {
+    /// The instruction refers to a register that the device does not have.
+    InvalidRegister { register: i64, count: usize },
+    /// The operation of the instruction is not known.
+    UnknownOperation(i64),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceError::InvalidRegister { register, count } => write!(
+                f,
+                "invalid register {} for a device with {} registers",
+                register, count
+            ),
+            DeviceError::UnknownOperation(o) => write!(f, "unknown operation: {}", o),
+        }
+    }
+}
+
+/// A program, optionally with its instruction pointer bound to a register.
+/// See the `elfcode` module for its textual form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub ip: Option<usize>,
+    pub instructions: Vec<Instruction>,
+}
+
+impl Program {
+    /// Run the program on the device until it halts.
+    pub fn run(&self, device: Device) -> Result<Device, DeviceError> {
+        self.run_with(device, |_, _| true)
+    }
+
+    /// Run the program on the device until it halts. Every time the
+    /// instruction at index `at` is about to be executed, `f` is called with
+    /// the current state of the device; execution stops early when it returns
+    /// false.
+    pub fn trace<F>(&self, device: Device, at: usize, mut f: F) -> Result<Device, DeviceError>
+    where
+        F: FnMut(&Device) -> bool,
+    {
+        self.run_with(device, |ip, d| ip != at || f(d))
+    }
+
+    /// Execute the instruction at index `ip`, returning the resulting state and
+    /// the index of the next instruction, or `None` if the program halts.
+    pub fn step(&self, device: &Device, ip: usize) -> Result<(Device, Option<usize>), DeviceError> {
+        let mut device = device.clone();
+        if let Some(r) = self.ip {
+            device.set_register(r as i64, ip as i64)?;
+        }
+        let device = device.execute(&self.instructions[ip])?;
+        let next = match self.ip {
+            Some(r) => device.register(r as i64)? + 1,
+            None => ip as i64 + 1,
+        };
+        if next < 0 || next as usize >= self.instructions.len() {
+            Ok((device, None))
+        } else {
+            Ok((device, Some(next as usize)))
+        }
+    }
+
+    fn run_with<F>(&self, mut device: Device, mut f: F) -> Result<Device, DeviceError>
+    where
+        F: FnMut(usize, &Device) -> bool,
+    {
+        let mut ip = if self.instructions.is_empty() {
+            None
+        } else {
+            Some(0)
+        };
+        while let Some(i) = ip {
+            if let Some(r) = self.ip {
+                device.set_register(r as i64, i as i64)?;
             }
-            Operation::EQIR => {
-                result.registers[i.c as usize] = if i.a == result.registers[i.b as usize] {
-                    1
-                } else {
-                    0
-                }
+            if !f(i, &device) {
+                break;
             }
-            Operation::EQRI => {
-                result.registers[i.c as usize] = if result.registers[i.a as usize] == i.b {
-                    1
-                } else {
-                    0
+            let (next_device, next_ip) = self.step(&device, i)?;
+            device = next_device;
+            ip = next_ip;
+        }
+        Ok(device)
+    }
+}
+
+#[test]
+fn test_invalid_register() {
+    let device = Device::new(4);
+    let i = Instruction::from_str("addr 1 4 0").unwrap();
+    assert_eq!(
+        Err(DeviceError::InvalidRegister {
+            register: 4,
+            count: 4
+        }),
+        device.execute(&i)
+    );
+    assert!(Device::new(6).execute(&i).is_ok());
+    let i = Instruction::from_str("seti 0 0 -1").unwrap();
+    assert!(device.execute(&i).is_err());
+}
+
+#[test]
+fn test_wrapping_u32() {
+    let device = Device::new(2).with_word(Word::WrappingU32);
+    let device = device
+        .execute(&Instruction::from_str("seti 4294967295 0 0").unwrap())
+        .unwrap()
+        .execute(&Instruction::from_str("addi 0 2 1").unwrap())
+        .unwrap();
+    assert_eq!(vec![4_294_967_295, 1], device.registers);
+    let device = device
+        .execute(&Instruction::from_str("mulr 0 0 0").unwrap())
+        .unwrap();
+    assert_eq!(1, device.registers[0]);
+}
+
+#[test]
+fn test_deduce_opcodes() {
+    let sample = |before: &str, i: &str, after: &str| Sample {
+        before: Device::from_str(before).unwrap(),
+        instruction: Instruction::from_str(i).unwrap(),
+        after: Device::from_str(after).unwrap(),
+    };
+    // ADDI, MULR or SETI.
+    let s5 = sample("[3, 2, 1, 1]", "5 2 1 2", "[3, 2, 2, 1]");
+    // ADDI or MULR.
+    let s7 = sample("[3, 1, 1, 2]", "7 0 3 2", "[3, 1, 6, 2]");
+    // MULR.
+    let s3 = sample("[3, 2, 1, 1]", "3 0 1 2", "[3, 2, 6, 1]");
+
+    let map = deduce_opcodes(&[s5.clone(), s7.clone(), s3.clone()]).unwrap();
+    assert_eq!(Some(&Operation::MULR), map.operation(3));
+    assert_eq!(Some(&Operation::SETI), map.operation(5));
+    assert_eq!(Some(&Operation::ADDI), map.operation(7));
+    assert_eq!(None, map.operation(4));
+
+    let ambiguity = deduce_opcodes(&[s5.clone(), s7.clone()]).unwrap_err();
+    assert_eq!(
+        vec![
+            (5, vec![Operation::ADDI, Operation::MULR, Operation::SETI]),
+            (7, vec![Operation::ADDI, Operation::MULR]),
+        ],
+        ambiguity.candidates
+    );
+    assert_eq!(
+        "opcode 5: addi, mulr, seti\nopcode 7: addi, mulr",
+        ambiguity.to_string()
+    );
+
+    let mut s8 = s3.clone();
+    s8.instruction.op = Operation::OP(8);
+    let ambiguity = deduce_opcodes(&[s3, s8]).unwrap_err();
+    assert_eq!(
+        vec![(3, vec![Operation::MULR]), (8, vec![Operation::MULR])],
+        ambiguity.candidates
+    );
+}
+
+#[test]
+fn test_generated_inputs() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    for seed in 0..5 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let map = random_opcode_map(&mut rng);
+        let options = GeneratorOptions {
+            samples: 200,
+            program_len: 50,
+            ..GeneratorOptions::default()
+        };
+
+        // Every sample of an unambiguous input identifies its opcode.
+        let unambiguous = GeneratorOptions {
+            candidates: 1..=1,
+            ..options.clone()
+        };
+        let input = generate_input(&map, &unambiguous, &mut rng).unwrap();
+        assert_eq!(0, num_samples(&input));
+        assert_eq!(Ok(&map), deduce_opcodes(&parse_samples(&input)).as_ref());
+        let program = input.split("\n\n\n\n").nth(1).unwrap();
+        let expected = elfcode::decode(program, &map)
+            .unwrap()
+            .run(Device::new(4))
+            .unwrap();
+        assert_eq!(expected.registers[0], result_r0(&input));
+
+        // The true operation is always among the candidates.
+        let ambiguous = GeneratorOptions {
+            samples: 16,
+            candidates: 3..=16,
+            ..options.clone()
+        };
+        let input = generate_input(&map, &ambiguous, &mut rng).unwrap();
+        assert_eq!(16, num_samples(&input));
+        match deduce_opcodes(&parse_samples(&input)) {
+            Ok(deduced) => assert_eq!(map, deduced),
+            Err(ambiguity) => {
+                for (o, ops) in ambiguity.candidates {
+                    assert!(ops.contains(map.operation(o).unwrap()));
                 }
             }
-            Operation::EQRR => {
-                result.registers[i.c as usize] =
-                    if result.registers[i.a as usize] == result.registers[i.b as usize] {
-                        1
-                    } else {
-                        0
-                    }
-            }
-            _ => panic!("not implemented"),
         }
-        result
     }
 }
+
+#[test]
+fn test_generator_rejects_options() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let map = random_opcode_map(&mut rng);
+    let no_samples = GeneratorOptions {
+        samples: 0,
+        ..GeneratorOptions::default()
+    };
+    assert_eq!(None, generate_input(&map, &no_samples, &mut rng));
+    let no_registers = GeneratorOptions {
+        registers: 0,
+        ..GeneratorOptions::default()
+    };
+    assert_eq!(None, generate_input(&map, &no_registers, &mut rng));
+}
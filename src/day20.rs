@@ -0,0 +1,197 @@
+use petgraph::algo::dijkstra;
+use petgraph::graphmap::UnGraphMap;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// Find the largest number of doors required to pass through to reach a room.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day20::furthest_room;
+///
+/// assert_eq!(3, furthest_room("^WNE$"));
+/// assert_eq!(10, furthest_room("^ENWWW(NEEE|SSE(EE|N))$"));
+/// assert_eq!(18, furthest_room("^ENNWSWW(NEWS|)SSSEEN(WNSE|)EE(SWEN|)NNN$"));
+/// assert_eq!(15, furthest_room("^ESSWWN(E|NNENN(EESS(WNSW|)SSS|WWWSSSSE(SW|NNNE)))$"));
+/// assert_eq!(31, furthest_room("^WSSEESWWWNW(S|NENNEEEENN(ESSSSW(NWSW|SSEN)|WSWWN(E|WWS(E|SS))))$"));
+/// ```
+pub fn furthest_room(input: &str) -> usize {
+    let map = RoomMap::from_str(input).unwrap();
+    map.distances()
+        .into_iter()
+        .map(|(_, d)| d)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Find the number of rooms which require passing through at least 1000
+/// doors.
+pub fn num_far_rooms(input: &str) -> usize {
+    let map = RoomMap::from_str(input).unwrap();
+    map.distances()
+        .into_iter()
+        .filter(|(_, d)| *d >= 1000)
+        .count()
+}
+
+/// A room, identified by its x and y coordinates relative to the starting room.
+pub type Room = (i64, i64);
+
+/// Map of the rooms in the facility, with an edge for every door.
+pub struct RoomMap {
+    graph: UnGraphMap<Room, ()>,
+}
+
+impl FromStr for RoomMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut graph = UnGraphMap::new();
+        graph.add_node((0, 0));
+        // The rooms the route may have reached so far, and per open group the
+        // rooms it started from together with those where its options ended.
+        let mut current: BTreeSet<Room> = vec![(0, 0)].into_iter().collect();
+        let mut groups: Vec<(BTreeSet<Room>, BTreeSet<Room>)> = vec![];
+        for ch in s
+            .trim()
+            .trim_start_matches('^')
+            .trim_end_matches('$')
+            .chars()
+        {
+            let (dx, dy) = match ch {
+                'N' => (0, -1),
+                'S' => (0, 1),
+                'E' => (1, 0),
+                'W' => (-1, 0),
+                '(' => {
+                    groups.push((current.clone(), BTreeSet::new()));
+                    continue;
+                }
+                '|' => {
+                    let (start, ends) = groups.last_mut().ok_or("unexpected '|'")?;
+                    ends.extend(current);
+                    current = start.clone();
+                    continue;
+                }
+                ')' => {
+                    // The route goes on from the end of every option, which
+                    // for an empty option is where the group started.
+                    let (_, ends) = groups.pop().ok_or("unexpected ')'")?;
+                    current.extend(ends);
+                    continue;
+                }
+                _ => return Err(format!("unknown direction: {}", ch)),
+            };
+            current = current
+                .into_iter()
+                .map(|room| {
+                    let next = (room.0 + dx, room.1 + dy);
+                    graph.add_edge(room, next, ());
+                    next
+                })
+                .collect();
+        }
+        if !groups.is_empty() {
+            return Err(String::from("unterminated branch"));
+        }
+        Ok(RoomMap { graph })
+    }
+}
+
+impl RoomMap {
+    /// The underlying graph of rooms and doors.
+    pub fn graph(&self) -> &UnGraphMap<Room, ()> {
+        &self.graph
+    }
+
+    /// Number of doors on the shortest path from the starting room to every
+    /// room.
+    pub fn distances(&self) -> Vec<(Room, usize)> {
+        dijkstra(&self.graph, (0, 0), None, |_| 1)
+            .into_iter()
+            .collect()
+    }
+
+    fn bounds(&self) -> (i64, i64, i64, i64) {
+        self.graph.nodes().fold(
+            (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+            |(min_x, min_y, max_x, max_y), (x, y)| {
+                (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+            },
+        )
+    }
+}
+
+/// Render the map the way it is drawn in the puzzle.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day20::RoomMap;
+/// use std::str::FromStr;
+///
+/// let map = RoomMap::from_str("^ENWWW(NEEE|SSE(EE|N))$").unwrap();
+/// let expected = [
+///     "#########",
+///     "#.|.|.|.#",
+///     "#-#######",
+///     "#.|.|.|.#",
+///     "#-#####-#",
+///     "#.#.#X|.#",
+///     "#-#-#####",
+///     "#.|.|.|.#",
+///     "#########",
+/// ];
+/// assert_eq!(expected.join("\n") + "\n", map.to_string());
+/// ```
+impl fmt::Display for RoomMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (min_x, min_y, max_x, max_y) = self.bounds();
+        let width = (2 * (max_x - min_x) + 3) as usize;
+        let height = (2 * (max_y - min_y) + 3) as usize;
+        let mut cells = vec![vec!['#'; width]; height];
+        for (x, y) in self.graph.nodes() {
+            let c = (2 * (x - min_x) + 1) as usize;
+            let r = (2 * (y - min_y) + 1) as usize;
+            cells[r][c] = if (x, y) == (0, 0) { 'X' } else { '.' };
+            if self.graph.contains_edge((x, y), (x + 1, y)) {
+                cells[r][c + 1] = '|';
+            }
+            if self.graph.contains_edge((x, y), (x, y + 1)) {
+                cells[r + 1][c] = '-';
+            }
+        }
+        let repr: String = cells
+            .iter()
+            .map(|r| r.iter().collect::<String>() + "\n")
+            .collect();
+        write!(f, "{}", repr)
+    }
+}
+
+#[test]
+fn test_num_far_rooms() {
+    assert_eq!(0, num_far_rooms("^ENWWW(NEEE|SSE(EE|N))$"));
+    let input = format!("^{}(N|S)$", "E".repeat(1000));
+    assert_eq!(3, num_far_rooms(&input));
+}
+
+#[test]
+fn test_route_continues_after_every_alternative() {
+    assert_eq!(4, furthest_room("^(N|S)EEE$"));
+    let map = RoomMap::from_str("^(N|S)E$").unwrap();
+    assert!(map.graph().contains_edge((0, -1), (1, -1)));
+    assert!(map.graph().contains_edge((0, 1), (1, 1)));
+    assert!(!map.graph().contains_edge((0, 0), (1, 0)));
+
+    // An empty option continues from where the group started.
+    let map = RoomMap::from_str("^N(EEN|)N$").unwrap();
+    assert!(map.graph().contains_edge((0, -1), (0, -2)));
+    assert!(map.graph().contains_edge((2, -2), (2, -3)));
+    let map = RoomMap::from_str("^(N|S|)E$").unwrap();
+    assert!(map.graph().contains_edge((0, -1), (1, -1)));
+    assert!(map.graph().contains_edge((0, 1), (1, 1)));
+    assert!(map.graph().contains_edge((0, 0), (1, 0)));
+}
@@ -0,0 +1,104 @@
+use crate::day16::{Device, Operation, Program};
+use crate::elfcode::vm::Machine;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Find the lowest value for register 0 that causes the program to halt after
+/// executing the fewest instructions.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day21::fewest_instructions_r0;
+///
+/// assert_eq!(1, fewest_instructions_r0("#ip 1
+/// seti 0 0 3
+/// muli 3 5 3
+/// addi 3 1 3
+/// bani 3 15 3
+/// eqrr 3 0 2
+/// addr 2 1 1
+/// seti 0 0 1"));
+/// ```
+pub fn fewest_instructions_r0(input: &str) -> i64 {
+    let mut first = None;
+    trace_halting_values(input, |v| {
+        first = Some(v);
+        false
+    });
+    first.expect("program never reaches the halting comparison")
+}
+
+/// Find the lowest value for register 0 that causes the program to halt after
+/// executing the most instructions.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day21::most_instructions_r0;
+///
+/// assert_eq!(0, most_instructions_r0("#ip 1
+/// seti 0 0 3
+/// muli 3 5 3
+/// addi 3 1 3
+/// bani 3 15 3
+/// eqrr 3 0 2
+/// addr 2 1 1
+/// seti 0 0 1"));
+/// ```
+pub fn most_instructions_r0(input: &str) -> i64 {
+    let mut seen = HashSet::new();
+    let mut last = None;
+    trace_halting_values(input, |v| {
+        if seen.insert(v) {
+            last = Some(v);
+            true
+        } else {
+            false
+        }
+    });
+    last.expect("program never reaches the halting comparison")
+}
+
+/// Run the program, calling `f` with the value that register 0 is compared
+/// against every time the halting comparison is reached. Execution stops when
+/// `f` returns false.
+fn trace_halting_values<F>(input: &str, mut f: F)
+where
+    F: FnMut(i64) -> bool,
+{
+    let program = Program::from_str(input).unwrap();
+    let (at, register) = halting_comparison(&program).expect("no halting comparison");
+    let mut machine = Machine::optimized(&program, Device::new(6)).unwrap();
+    // A value the comparison never matches, so that the program does not halt
+    // before `f` has seen every value.
+    machine.registers_mut()[0] = -1;
+    machine.run_while(|ip, r| ip != at || f(r[register]));
+}
+
+/// Find the index of the instruction that compares a register against register
+/// 0, along with the other register.
+fn halting_comparison(program: &Program) -> Option<(usize, usize)> {
+    program
+        .instructions
+        .iter()
+        .enumerate()
+        .find_map(|(i, ins)| match (&ins.op, ins.a, ins.b) {
+            (Operation::EQRR, 0, r) | (Operation::EQRR, r, 0) => Some((i, r as usize)),
+            _ => None,
+        })
+}
+
+#[test]
+fn test_halting_value_zero() {
+    let program = "#ip 1
+seti 3 0 3
+muli 3 5 3
+addi 3 1 3
+bani 3 15 3
+eqrr 3 0 2
+addr 2 1 1
+seti 0 0 1";
+    assert_eq!(0, fewest_instructions_r0(program));
+    assert_eq!(3, most_instructions_r0(program));
+}
@@ -0,0 +1,263 @@
+use pathfinding::prelude::dijkstra;
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+/// Determine the total risk level of the rectangle from the cave mouth to the
+/// target.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day22::risk_level;
+///
+/// assert_eq!(114, risk_level("depth: 510
+/// target: 10,10"));
+/// ```
+pub fn risk_level(input: &str) -> usize {
+    let cave = Cave::from_str(input).unwrap();
+    let (tx, ty) = cave.target;
+    let mut risk = 0;
+    for y in 0..=ty {
+        for x in 0..=tx {
+            risk += cave.region(x, y) as usize;
+        }
+    }
+    risk
+}
+
+/// Determine the fewest number of minutes to reach the target.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day22::rescue_time;
+///
+/// assert_eq!(45, rescue_time("depth: 510
+/// target: 10,10"));
+/// ```
+pub fn rescue_time(input: &str) -> usize {
+    let cave = Cave::from_str(input).unwrap();
+    let target = (cave.target, Tool::Torch);
+    let (_, time) = dijkstra(
+        &((0, 0), Tool::Torch),
+        |&((x, y), tool)| {
+            let mut next = vec![];
+            let region = cave.region(x, y);
+            for &other in &[Tool::Torch, Tool::ClimbingGear, Tool::Neither] {
+                if other != tool && region.allows(other) {
+                    next.push((((x, y), other), 7));
+                }
+            }
+            let mut adjacent = vec![(x + 1, y), (x, y + 1)];
+            if x > 0 {
+                adjacent.push((x - 1, y));
+            }
+            if y > 0 {
+                adjacent.push((x, y - 1));
+            }
+            for (ax, ay) in adjacent {
+                if cave.region(ax, ay).allows(tool) {
+                    next.push((((ax, ay), tool), 1));
+                }
+            }
+            next
+        },
+        |s| *s == target,
+    )
+    .unwrap();
+    time
+}
+
+/// Type of a region in the cave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    Rocky = 0,
+    Wet = 1,
+    Narrow = 2,
+}
+
+impl Region {
+    fn allows(self, tool: Tool) -> bool {
+        match self {
+            Region::Rocky => tool != Tool::Neither,
+            Region::Wet => tool != Tool::Torch,
+            Region::Narrow => tool != Tool::ClimbingGear,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Tool {
+    Torch,
+    ClimbingGear,
+    Neither,
+}
+
+/// Model of the cave system. Erosion levels are computed on demand, so the cave
+/// can be explored beyond the target.
+pub struct Cave {
+    depth: usize,
+    target: (usize, usize),
+    /// Erosion levels computed so far, by row. All rows have the same length.
+    erosion: RefCell<Vec<Vec<usize>>>,
+}
+
+impl FromStr for Cave {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut depth = None;
+        let mut target = None;
+        for line in s.trim().split('\n') {
+            let parts: Vec<&str> = line.split(": ").collect();
+            if parts.len() != 2 {
+                return Err(format!("malformed line: {}", line));
+            }
+            match parts[0].trim() {
+                "depth" => depth = parts[1].trim().parse().ok(),
+                "target" => {
+                    let c: Vec<usize> = parts[1]
+                        .split(',')
+                        .filter_map(|x| x.trim().parse().ok())
+                        .collect();
+                    if c.len() == 2 {
+                        target = Some((c[0], c[1]));
+                    }
+                }
+                k => return Err(format!("unknown key: {}", k)),
+            }
+        }
+        match (depth, target) {
+            (Some(depth), Some(target)) => Ok(Cave::new(depth, target)),
+            _ => Err(String::from("missing depth or target")),
+        }
+    }
+}
+
+impl Cave {
+    /// Create a cave with the given depth and target coordinates.
+    pub fn new(depth: usize, target: (usize, usize)) -> Self {
+        Cave {
+            depth,
+            target,
+            erosion: RefCell::new(vec![]),
+        }
+    }
+
+    /// Geologic index of the region at the given coordinates.
+    pub fn geologic_index(&self, x: usize, y: usize) -> usize {
+        if x == 0 || y == 0 {
+            self.index_from(x, y, 0, 0)
+        } else {
+            self.index_from(
+                x,
+                y,
+                self.erosion_level(x - 1, y),
+                self.erosion_level(x, y - 1),
+            )
+        }
+    }
+
+    /// Geologic index of the region at the given coordinates, given the
+    /// erosion levels of the regions to the left and above it.
+    fn index_from(&self, x: usize, y: usize, left: usize, up: usize) -> usize {
+        if (x, y) == (0, 0) || (x, y) == self.target {
+            0
+        } else if y == 0 {
+            x * 16807
+        } else if x == 0 {
+            y * 48271
+        } else {
+            left * up
+        }
+    }
+
+    /// Erosion level of the region at the given coordinates.
+    pub fn erosion_level(&self, x: usize, y: usize) -> usize {
+        if let Some(&e) = self.erosion.borrow().get(y).and_then(|row| row.get(x)) {
+            return e;
+        }
+        self.extend(x, y);
+        self.erosion.borrow()[y][x]
+    }
+
+    /// Compute the erosion levels up to the given coordinates, row by row.
+    fn extend(&self, x: usize, y: usize) {
+        let mut erosion = self.erosion.borrow_mut();
+        let width = erosion.first().map_or(0, Vec::len).max(x + 1);
+        let height = erosion.len().max(y + 1);
+        for r in 0..height {
+            if r == erosion.len() {
+                erosion.push(Vec::with_capacity(width));
+            }
+            for c in erosion[r].len()..width {
+                let (left, up) = if c > 0 && r > 0 {
+                    (erosion[r][c - 1], erosion[r - 1][c])
+                } else {
+                    (0, 0)
+                };
+                let e = (self.index_from(c, r, left, up) + self.depth) % 20183;
+                erosion[r].push(e);
+            }
+        }
+    }
+
+    /// Type of the region at the given coordinates.
+    pub fn region(&self, x: usize, y: usize) -> Region {
+        match self.erosion_level(x, y) % 3 {
+            0 => Region::Rocky,
+            1 => Region::Wet,
+            _ => Region::Narrow,
+        }
+    }
+}
+
+/// Render the regions from the cave mouth to the target the way they are drawn
+/// in the puzzle.
+impl fmt::Display for Cave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (tx, ty) = self.target;
+        let repr: String = (0..=ty)
+            .map(|y| {
+                (0..=tx)
+                    .map(|x| match ((x, y), self.region(x, y)) {
+                        ((0, 0), _) => 'M',
+                        (p, _) if p == self.target => 'T',
+                        (_, Region::Rocky) => '.',
+                        (_, Region::Wet) => '=',
+                        (_, Region::Narrow) => '|',
+                    })
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect();
+        write!(f, "{}", repr)
+    }
+}
+
+#[test]
+fn test_cave_display() {
+    let cave = Cave::new(510, (10, 10));
+    let expected = "M=.|=.|.|=.
+.|=|=|||..|
+.==|....||=
+=.|....|.==
+=|..==...=.
+=||.=.=||=|
+|.=.===|||.
+|..==||=.|=
+.=..===..=|
+.======|||=
+.===|=|===T
+";
+    assert_eq!(expected, cave.to_string());
+}
+
+#[test]
+fn test_far_region() {
+    let cave = Cave::new(5000, (10, 800));
+    let e = cave.erosion_level(10, 200_000);
+    assert_eq!((cave.geologic_index(10, 200_000) + 5000) % 20183, e);
+    assert_eq!(e, cave.erosion_level(10, 200_000));
+}
@@ -0,0 +1,238 @@
+use regex::{Error, Regex};
+use std::collections::BinaryHeap;
+use std::str::FromStr;
+
+/// Find the number of nanobots in range of the nanobot with the largest signal
+/// radius.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day23::num_in_range;
+///
+/// assert_eq!(7, num_in_range("pos=<0,0,0>, r=4
+/// pos=<1,0,0>, r=1
+/// pos=<4,0,0>, r=3
+/// pos=<0,2,0>, r=1
+/// pos=<0,5,0>, r=3
+/// pos=<0,0,3>, r=1
+/// pos=<1,1,1>, r=1
+/// pos=<1,1,2>, r=1
+/// pos=<1,3,1>, r=1"));
+/// ```
+pub fn num_in_range(input: &str) -> usize {
+    let bots = parse_bots(input);
+    let strongest = bots.iter().max_by_key(|b| b.radius).unwrap();
+    bots.iter()
+        .filter(|b| strongest.pos.dist(&b.pos) <= strongest.radius)
+        .count()
+}
+
+/// Find the shortest distance from the origin to a point that is in range of
+/// the largest number of nanobots.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day23::best_position_dist;
+///
+/// assert_eq!(36, best_position_dist("pos=<10,12,12>, r=2
+/// pos=<12,14,12>, r=2
+/// pos=<16,12,12>, r=4
+/// pos=<14,14,14>, r=6
+/// pos=<50,50,50>, r=200
+/// pos=<10,10,10>, r=5"));
+/// ```
+pub fn best_position_dist(input: &str) -> i64 {
+    let bots = parse_bots(input);
+    let origin = Point3D { x: 0, y: 0, z: 0 };
+
+    // Start with a cube that covers every nanobot along with its range, and
+    // keep splitting the most promising cube into octants. The number of bots
+    // in range of a cube is an upper bound for any point inside it, so the
+    // first single point that is popped is the best one.
+    let extent = bots
+        .iter()
+        .map(|b| b.pos.x.abs().max(b.pos.y.abs()).max(b.pos.z.abs()) + b.radius)
+        .max()
+        .unwrap();
+    let mut size = 1;
+    while size < 2 * extent + 1 {
+        size *= 2;
+    }
+    let root = Cube {
+        min: Point3D {
+            x: -size / 2,
+            y: -size / 2,
+            z: -size / 2,
+        },
+        size,
+    };
+
+    let mut heap = BinaryHeap::new();
+    heap.push(Candidate::new(root, &bots, &origin));
+    while let Some(candidate) = heap.pop() {
+        if candidate.cube.size == 1 {
+            return candidate.dist;
+        }
+        for cube in candidate.cube.octants() {
+            heap.push(Candidate::new(cube, &bots, &origin));
+        }
+    }
+    panic!("no position in range of any nanobot")
+}
+
+/// A point in 3D space.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Point3D {
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+impl Point3D {
+    /// Manhattan distance to the other point.
+    pub fn dist(&self, other: &Self) -> i64 {
+        (self.x - other.x).abs() + (self.y - other.y).abs() + (self.z - other.z).abs()
+    }
+}
+
+/// A nanobot with its position and signal radius.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nanobot {
+    pub pos: Point3D,
+    pub radius: i64,
+}
+
+impl FromStr for Nanobot {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(
+            r"pos=<(?P<x>-?[[:digit:]]+),(?P<y>-?[[:digit:]]+),(?P<z>-?[[:digit:]]+)>, r=(?P<r>[[:digit:]]+)",
+        )?;
+        let caps = re.captures(s).unwrap();
+        Ok(Nanobot {
+            pos: Point3D {
+                x: caps["x"].parse().unwrap(),
+                y: caps["y"].parse().unwrap(),
+                z: caps["z"].parse().unwrap(),
+            },
+            radius: caps["r"].parse().unwrap(),
+        })
+    }
+}
+
+fn parse_bots(input: &str) -> Vec<Nanobot> {
+    input
+        .trim()
+        .split('\n')
+        .map(Nanobot::from_str)
+        .map(Result::unwrap)
+        .collect()
+}
+
+/// An axis-aligned cube of integer points.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cube {
+    min: Point3D,
+    size: i64,
+}
+
+impl Cube {
+    /// Manhattan distance from the point to the closest point in the cube.
+    fn dist(&self, p: &Point3D) -> i64 {
+        let axis = |v: i64, lo: i64| {
+            let hi = lo + self.size - 1;
+            if v < lo {
+                lo - v
+            } else if v > hi {
+                v - hi
+            } else {
+                0
+            }
+        };
+        axis(p.x, self.min.x) + axis(p.y, self.min.y) + axis(p.z, self.min.z)
+    }
+
+    fn octants(&self) -> Vec<Cube> {
+        let half = self.size / 2;
+        let mut result = vec![];
+        for &dx in &[0, half] {
+            for &dy in &[0, half] {
+                for &dz in &[0, half] {
+                    result.push(Cube {
+                        min: Point3D {
+                            x: self.min.x + dx,
+                            y: self.min.y + dy,
+                            z: self.min.z + dz,
+                        },
+                        size: half,
+                    });
+                }
+            }
+        }
+        result
+    }
+}
+
+/// A cube waiting to be searched, ordered so that the cube in range of the most
+/// nanobots, then closest to the origin, then smallest, comes first.
+#[derive(Debug, PartialEq, Eq)]
+struct Candidate {
+    count: usize,
+    dist: i64,
+    cube: Cube,
+}
+
+impl Candidate {
+    fn new(cube: Cube, bots: &[Nanobot], origin: &Point3D) -> Self {
+        Candidate {
+            count: bots
+                .iter()
+                .filter(|b| cube.dist(&b.pos) <= b.radius)
+                .count(),
+            dist: cube.dist(origin),
+            cube,
+        }
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.count
+            .cmp(&other.count)
+            .then(other.dist.cmp(&self.dist))
+            .then(other.cube.size.cmp(&self.cube.size))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[test]
+fn test_best_position_dist_brute_force() {
+    let input = "pos=<0,0,0>, r=3
+pos=<5,0,0>, r=3
+pos=<2,6,-1>, r=4
+pos=<-3,-3,2>, r=5
+pos=<3,2,1>, r=2";
+    let bots = parse_bots(input);
+    let mut best = (0, 0);
+    for x in -15..=15 {
+        for y in -15..=15 {
+            for z in -15..=15 {
+                let p = Point3D { x, y, z };
+                let count = bots.iter().filter(|b| b.pos.dist(&p) <= b.radius).count();
+                let dist = p.dist(&Point3D { x: 0, y: 0, z: 0 });
+                if count > best.0 || (count == best.0 && dist < best.1) {
+                    best = (count, dist);
+                }
+            }
+        }
+    }
+    assert_eq!(best.1, best_position_dist(input));
+}
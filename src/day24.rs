@@ -0,0 +1,454 @@
+use regex::Regex;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Determine the number of units the winning army has left.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day24::winning_units;
+///
+/// assert_eq!(5216, winning_units("Immune System:
+/// 17 units each with 5390 hit points (weak to radiation, bludgeoning) with an attack that does 4507 fire damage at initiative 2
+/// 989 units each with 1274 hit points (immune to fire; weak to bludgeoning, slashing) with an attack that does 25 slashing damage at initiative 3
+///
+/// Infection:
+/// 801 units each with 4706 hit points (weak to radiation) with an attack that does 116 bludgeoning damage at initiative 1
+/// 4485 units each with 2961 hit points (immune to radiation; weak to fire, cold) with an attack that does 12 slashing damage at initiative 4"));
+/// ```
+pub fn winning_units(input: &str) -> usize {
+    let mut battle = Battle::from_str(input).unwrap();
+    match battle.fight() {
+        Outcome::Victory(_, units) => units,
+        Outcome::Stalemate => 0,
+    }
+}
+
+/// Determine the number of units the immune system has left with the smallest
+/// boost that lets it win, if any boost does.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day24::boosted_immune_units;
+///
+/// assert_eq!(Some(51), boosted_immune_units("Immune System:
+/// 17 units each with 5390 hit points (weak to radiation, bludgeoning) with an attack that does 4507 fire damage at initiative 2
+/// 989 units each with 1274 hit points (immune to fire; weak to bludgeoning, slashing) with an attack that does 25 slashing damage at initiative 3
+///
+/// Infection:
+/// 801 units each with 4706 hit points (weak to radiation) with an attack that does 116 bludgeoning damage at initiative 1
+/// 4485 units each with 2961 hit points (immune to radiation; weak to fire, cold) with an attack that does 12 slashing damage at initiative 4"));
+/// ```
+pub fn boosted_immune_units(input: &str) -> Option<usize> {
+    let battle = Battle::from_str(input).unwrap();
+    let (_, units) = battle.smallest_winning_boost(Army::ImmuneSystem)?;
+    Some(units)
+}
+
+/// The side a group fights for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Army {
+    ImmuneSystem,
+    Infection,
+}
+
+/// Identifies a group by its army and its 1-based number within that army, as
+/// in the puzzle's battle log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GroupId {
+    pub army: Army,
+    pub number: usize,
+}
+
+/// A group of identical units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    pub id: GroupId,
+    pub units: usize,
+    pub hit_points: usize,
+    pub damage: usize,
+    pub attack_type: String,
+    pub initiative: usize,
+    pub weaknesses: HashSet<String>,
+    pub immunities: HashSet<String>,
+}
+
+impl Group {
+    fn parse(s: &str, id: GroupId) -> Result<Self, String> {
+        let re = Regex::new(
+            r"^(?P<units>\d+) units each with (?P<hp>\d+) hit points (\((?P<traits>[^)]*)\) )?with an attack that does (?P<damage>\d+) (?P<type>\w+) damage at initiative (?P<initiative>\d+)$",
+        )
+        .unwrap();
+        let caps = re
+            .captures(s.trim())
+            .ok_or_else(|| format!("malformed group: {}", s))?;
+        let mut weaknesses = HashSet::new();
+        let mut immunities = HashSet::new();
+        if let Some(traits) = caps.name("traits") {
+            for t in traits.as_str().split("; ") {
+                let (set, list) = if let Some(list) = t.strip_prefix("weak to ") {
+                    (&mut weaknesses, list)
+                } else if let Some(list) = t.strip_prefix("immune to ") {
+                    (&mut immunities, list)
+                } else {
+                    return Err(format!("unknown trait: {}", t));
+                };
+                set.extend(list.split(", ").map(String::from));
+            }
+        }
+        Ok(Group {
+            id,
+            units: caps["units"].parse().unwrap(),
+            hit_points: caps["hp"].parse().unwrap(),
+            damage: caps["damage"].parse().unwrap(),
+            attack_type: caps["type"].to_string(),
+            initiative: caps["initiative"].parse().unwrap(),
+            weaknesses,
+            immunities,
+        })
+    }
+
+    /// Number of units times their attack damage.
+    pub fn effective_power(&self) -> usize {
+        self.units * self.damage
+    }
+
+    /// Damage this group would deal to the given defending group.
+    pub fn damage_to(&self, defender: &Group) -> usize {
+        if defender.immunities.contains(&self.attack_type) {
+            0
+        } else if defender.weaknesses.contains(&self.attack_type) {
+            2 * self.effective_power()
+        } else {
+            self.effective_power()
+        }
+    }
+}
+
+/// Something that happened during a round of the battle.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A group chose a target during the target selection phase.
+    TargetSelected {
+        attacker: GroupId,
+        defender: GroupId,
+        damage: usize,
+    },
+    /// A group attacked its target during the attacking phase.
+    Attacked {
+        attacker: GroupId,
+        defender: GroupId,
+        units_killed: usize,
+    },
+    /// A group lost all of its units.
+    Eliminated(GroupId),
+    /// The round finished.
+    RoundCompleted { round: usize, units_killed: usize },
+}
+
+/// How the battle ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The army won with the given number of units left.
+    Victory(Army, usize),
+    /// A full round passed without any unit dying.
+    Stalemate,
+}
+
+/// State of the battle between the two armies.
+#[derive(Clone, Debug)]
+pub struct Battle {
+    groups: Vec<Group>,
+    rounds: usize,
+}
+
+impl FromStr for Battle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut groups = vec![];
+        for section in s.trim().split("\n\n") {
+            let mut lines = section.trim().split('\n');
+            let army = match lines.next().map(str::trim) {
+                Some("Immune System:") => Army::ImmuneSystem,
+                Some("Infection:") => Army::Infection,
+                h => return Err(format!("unknown army: {:?}", h)),
+            };
+            for (i, l) in lines.filter(|l| !l.trim().is_empty()).enumerate() {
+                groups.push(Group::parse(
+                    l,
+                    GroupId {
+                        army,
+                        number: i + 1,
+                    },
+                )?);
+            }
+        }
+        Ok(Battle { groups, rounds: 0 })
+    }
+}
+
+impl Battle {
+    /// Groups that still have units left.
+    pub fn groups(&self) -> impl Iterator<Item = &Group> {
+        self.groups.iter().filter(|g| g.units > 0)
+    }
+
+    /// Number of rounds fought so far.
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    /// Add the given boost to the attack damage of every group in the army.
+    pub fn boost(&mut self, army: Army, boost: usize) {
+        for g in self.groups.iter_mut().filter(|g| g.id.army == army) {
+            g.damage += boost;
+        }
+    }
+
+    /// The battle outcome if it is over.
+    pub fn outcome(&self) -> Option<Outcome> {
+        let armies = self.groups().map(|g| g.id.army).collect::<HashSet<_>>();
+        if armies.len() > 1 {
+            return None;
+        }
+        let army = *armies.iter().next()?;
+        Some(Outcome::Victory(army, self.groups().map(|g| g.units).sum()))
+    }
+
+    /// Fight a single round: target selection followed by attacking.
+    pub fn round(&mut self) -> Vec<Event> {
+        let mut events = vec![];
+
+        // Target selection.
+        let mut order: Vec<usize> = (0..self.groups.len())
+            .filter(|&i| self.groups[i].units > 0)
+            .collect();
+        order.sort_by_key(|&i| {
+            let g = &self.groups[i];
+            std::cmp::Reverse((g.effective_power(), g.initiative))
+        });
+        let mut targets: Vec<Option<usize>> = vec![None; self.groups.len()];
+        let mut chosen = HashSet::new();
+        for &i in &order {
+            let attacker = &self.groups[i];
+            let target = (0..self.groups.len())
+                .filter(|&j| {
+                    let d = &self.groups[j];
+                    d.units > 0 && d.id.army != attacker.id.army && !chosen.contains(&j)
+                })
+                .map(|j| (attacker.damage_to(&self.groups[j]), j))
+                .filter(|&(damage, _)| damage > 0)
+                .max_by_key(|&(damage, j)| {
+                    let d = &self.groups[j];
+                    (damage, d.effective_power(), d.initiative)
+                });
+            if let Some((damage, j)) = target {
+                chosen.insert(j);
+                targets[i] = Some(j);
+                events.push(Event::TargetSelected {
+                    attacker: attacker.id,
+                    defender: self.groups[j].id,
+                    damage,
+                });
+            }
+        }
+
+        // Attacking.
+        order.sort_by_key(|&i| std::cmp::Reverse(self.groups[i].initiative));
+        let mut total_killed = 0;
+        for &i in &order {
+            let j = match targets[i] {
+                Some(j) if self.groups[i].units > 0 => j,
+                _ => continue,
+            };
+            let damage = self.groups[i].damage_to(&self.groups[j]);
+            let defender = &mut self.groups[j];
+            let units_killed = std::cmp::min(damage / defender.hit_points, defender.units);
+            defender.units -= units_killed;
+            total_killed += units_killed;
+            events.push(Event::Attacked {
+                attacker: self.groups[i].id,
+                defender: self.groups[j].id,
+                units_killed,
+            });
+            if self.groups[j].units == 0 {
+                events.push(Event::Eliminated(self.groups[j].id));
+            }
+        }
+
+        self.rounds += 1;
+        events.push(Event::RoundCompleted {
+            round: self.rounds,
+            units_killed: total_killed,
+        });
+        events
+    }
+
+    /// Fight until one army is left or a round passes without any casualties.
+    pub fn fight(&mut self) -> Outcome {
+        self.fight_with(|_| {})
+    }
+
+    /// Fight like `fight`, passing the events of every round to `f`.
+    pub fn fight_with<F>(&mut self, mut f: F) -> Outcome
+    where
+        F: FnMut(&[Event]),
+    {
+        loop {
+            if let Some(outcome) = self.outcome() {
+                return outcome;
+            }
+            let events = self.round();
+            f(&events);
+            if let Some(Event::RoundCompleted {
+                units_killed: 0, ..
+            }) = events.last()
+            {
+                return Outcome::Stalemate;
+            }
+        }
+    }
+
+    /// Find the smallest boost that lets the given army win, along with the
+    /// number of units it has left. Returns `None` if no boost does.
+    pub fn smallest_winning_boost(&self, army: Army) -> Option<(usize, usize)> {
+        let wins = |boost: usize| {
+            let mut battle = self.clone();
+            battle.boost(army, boost);
+            match battle.fight() {
+                Outcome::Victory(a, units) if a == army => Some(units),
+                _ => None,
+            }
+        };
+
+        let mut lo = 0;
+        if let Some(units) = wins(lo) {
+            return Some((lo, units));
+        }
+        let enemies: Vec<&Group> = self.groups().filter(|g| g.id.army != army).collect();
+        let can_hurt = self.groups().filter(|g| g.id.army == army).any(|g| {
+            enemies
+                .iter()
+                .any(|e| !e.immunities.contains(&g.attack_type))
+        });
+        if !can_hurt {
+            return None;
+        }
+        // With this boost a single unit wipes out any enemy group it attacks,
+        // so a larger boost makes no difference.
+        let cap = enemies
+            .iter()
+            .map(|e| e.hit_points * e.units)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let mut hi = 1;
+        let mut result = loop {
+            if let Some(units) = wins(hi) {
+                break units;
+            }
+            if hi >= cap {
+                return None;
+            }
+            lo = hi;
+            hi = std::cmp::min(2 * hi, cap);
+        };
+        // Invariant: boost `lo` loses and boost `hi` wins.
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            match wins(mid) {
+                Some(units) => {
+                    hi = mid;
+                    result = units;
+                }
+                None => lo = mid,
+            }
+        }
+        Some((hi, result))
+    }
+}
+
+#[test]
+fn test_round_events() {
+    let mut battle = Battle::from_str(
+        "Immune System:
+17 units each with 5390 hit points (weak to radiation, bludgeoning) with an attack that does 4507 fire damage at initiative 2
+989 units each with 1274 hit points (immune to fire; weak to bludgeoning, slashing) with an attack that does 25 slashing damage at initiative 3
+
+Infection:
+801 units each with 4706 hit points (weak to radiation) with an attack that does 116 bludgeoning damage at initiative 1
+4485 units each with 2961 hit points (immune to radiation; weak to fire, cold) with an attack that does 12 slashing damage at initiative 4",
+    )
+    .unwrap();
+    let immune = |number| GroupId {
+        army: Army::ImmuneSystem,
+        number,
+    };
+    let infection = |number| GroupId {
+        army: Army::Infection,
+        number,
+    };
+    let attacks: Vec<_> = battle
+        .round()
+        .into_iter()
+        .filter_map(|e| match e {
+            Event::Attacked {
+                attacker,
+                defender,
+                units_killed,
+            } => Some((attacker, defender, units_killed)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            (infection(2), immune(2), 84),
+            (immune(2), infection(1), 4),
+            (immune(1), infection(2), 51),
+            (infection(1), immune(1), 17),
+        ],
+        attacks
+    );
+}
+
+#[test]
+fn test_stalemate() {
+    let mut battle = Battle::from_str(
+        "Immune System:
+10 units each with 100 hit points (immune to fire) with an attack that does 1 cold damage at initiative 2
+
+Infection:
+10 units each with 100 hit points (immune to cold) with an attack that does 1 fire damage at initiative 1",
+    )
+    .unwrap();
+    assert_eq!(Outcome::Stalemate, battle.fight());
+    assert_eq!(1, battle.rounds());
+}
+
+#[test]
+fn test_no_winning_boost() {
+    let battle = Battle::from_str(
+        "Immune System:
+10 units each with 100 hit points with an attack that does 1 cold damage at initiative 2
+
+Infection:
+10 units each with 100 hit points (immune to cold) with an attack that does 1 fire damage at initiative 1",
+    )
+    .unwrap();
+    assert_eq!(None, battle.smallest_winning_boost(Army::ImmuneSystem));
+
+    // The infection strikes first and wipes out the immune system no matter
+    // how hard it could hit back.
+    let battle = Battle::from_str(
+        "Immune System:
+1 units each with 10 hit points with an attack that does 1 cold damage at initiative 1
+
+Infection:
+1 units each with 10 hit points with an attack that does 10 fire damage at initiative 2",
+    )
+    .unwrap();
+    assert_eq!(None, battle.smallest_winning_boost(Army::ImmuneSystem));
+}
@@ -0,0 +1,250 @@
+pub mod debugger;
+pub mod decompiler;
+pub mod idiom;
+pub mod trace;
+pub mod vm;
+
+use crate::day16::{Instruction, OpcodeMap, Operation, Program};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error while assembling, encoding or decoding a program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The source text is malformed at the given 1-based line.
+    Syntax { line: usize, message: String },
+    /// The opcode number is not present in the opcode map.
+    UnknownOpcode(i64),
+    /// The operation has no opcode number in the opcode map.
+    UnmappedOperation(Operation),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            Error::UnknownOpcode(o) => write!(f, "unknown opcode: {}", o),
+            Error::UnmappedOperation(op) => write!(f, "operation without opcode: {}", op),
+        }
+    }
+}
+
+/// Assemble the textual form of a program.
+///
+/// Every line holds an optional label definition (`name:`), an optional
+/// instruction and an optional comment starting with `;`. The `#ip N`
+/// directive binds the instruction pointer to register `N`. Operands are
+/// numbers, or labels with an optional offset (`loop-1`) which stand for the
+/// index of the instruction that follows the label.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::elfcode::assemble;
+///
+/// let program = assemble("#ip 3
+/// ; count r0 up to 5
+/// loop:
+///     addi 0 1 0
+///     gtri 0 4 1
+///     addr 1 3 3    ; done when r0 > 4
+///     seti loop-1 0 3").unwrap();
+/// assert_eq!(Some(3), program.ip);
+/// assert_eq!(4, program.instructions.len());
+/// assert_eq!("seti -1 0 3", program.instructions[3].to_string());
+/// ```
+pub fn assemble(source: &str) -> Result<Program, Error> {
+    let lines: Vec<(usize, &str)> = source
+        .split('\n')
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.split(';').next().unwrap().trim()))
+        .filter(|(_, l)| !l.is_empty())
+        .collect();
+
+    // First pass: find the directive, the labels and the instruction text.
+    let mut ip = None;
+    let mut labels = HashMap::new();
+    let mut statements = vec![];
+    for (line, mut text) in lines {
+        let syntax = |message: String| Error::Syntax { line, message };
+        if let Some(r) = text.strip_prefix("#ip") {
+            if ip.is_some() {
+                return Err(syntax(String::from("duplicate #ip directive")));
+            }
+            let r = r.trim();
+            ip = Some(
+                r.parse()
+                    .map_err(|_| syntax(format!("invalid register: {}", r)))?,
+            );
+            continue;
+        }
+        if let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_label(label) {
+                return Err(syntax(format!("invalid label: {}", label)));
+            }
+            if labels.insert(label, statements.len()).is_some() {
+                return Err(syntax(format!("duplicate label: {}", label)));
+            }
+            text = text[colon + 1..].trim();
+        }
+        if !text.is_empty() {
+            statements.push((line, text));
+        }
+    }
+
+    // Second pass: parse the instructions, resolving label operands.
+    let mut instructions = vec![];
+    for (line, text) in statements {
+        let syntax = |message: String| Error::Syntax { line, message };
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        if fields.len() != 4 {
+            return Err(syntax(format!(
+                "expected an operation and 3 operands: {}",
+                text
+            )));
+        }
+        let op = Operation::from_str(fields[0])
+            .map_err(|_| syntax(format!("unknown operation: {}", fields[0])))?;
+        let mut operands = [0; 3];
+        for (o, f) in operands.iter_mut().zip(&fields[1..]) {
+            *o = operand(f, &labels).ok_or_else(|| syntax(format!("invalid operand: {}", f)))?;
+        }
+        instructions.push(Instruction {
+            op,
+            a: operands[0],
+            b: operands[1],
+            c: operands[2],
+        });
+    }
+    Ok(Program { ip, instructions })
+}
+
+/// Decode a program whose operations are given as opcode numbers.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day16::{OpcodeMap, Operation};
+/// use aoc18::elfcode::{decode, encode};
+///
+/// let map = OpcodeMap::new(vec![Some(Operation::SETI), Some(Operation::ADDR)]);
+/// let program = decode("0 7 0 1\n1 1 1 0", &map).unwrap();
+/// assert_eq!("seti 7 0 1\naddr 1 1 0\n", program.to_string());
+/// assert_eq!("0 7 0 1\n1 1 1 0\n", encode(&program, &map).unwrap());
+/// ```
+pub fn decode(source: &str, map: &OpcodeMap) -> Result<Program, Error> {
+    let mut program = assemble(source)?;
+    for i in &mut program.instructions {
+        if let Operation::OP(o) = i.op {
+            i.op = map.operation(o).cloned().ok_or(Error::UnknownOpcode(o))?;
+        }
+    }
+    Ok(program)
+}
+
+/// Encode a program with its operations as opcode numbers.
+pub fn encode(program: &Program, map: &OpcodeMap) -> Result<String, Error> {
+    let mut numeric = program.clone();
+    for i in &mut numeric.instructions {
+        if let Operation::OP(_) = i.op {
+            continue;
+        }
+        let o = map
+            .opcode(&i.op)
+            .ok_or_else(|| Error::UnmappedOperation(i.op.clone()))?;
+        i.op = Operation::OP(o);
+    }
+    Ok(numeric.to_string())
+}
+
+fn is_label(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn operand(s: &str, labels: &HashMap<&str, usize>) -> Option<i64> {
+    if let Ok(v) = s.parse() {
+        return Some(v);
+    }
+    let (label, offset) = match s.find(['+', '-']) {
+        Some(i) => (&s[..i], s[i..].trim_start_matches('+').parse().ok()?),
+        None => (s, 0),
+    };
+    labels.get(label).map(|&v| v as i64 + offset)
+}
+
+impl FromStr for Program {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        assemble(s)
+    }
+}
+
+/// Print the program in the form accepted by `assemble`.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ip) = self.ip {
+            writeln!(f, "#ip {}", ip)?;
+        }
+        for i in &self.instructions {
+            writeln!(f, "{}", i)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let program = assemble(
+        "#ip 1
+seti 5 0 3   ; r3 = 5
+start: addr 3 0 0
+addi 3 -1 3
+gtri 3 0 2
+addr 2 1 1
+seti start-1 0 1",
+    )
+    .unwrap();
+    assert_eq!(program, assemble(&program.to_string()).unwrap());
+
+    let map = OpcodeMap::new(vec![
+        Some(Operation::SETI),
+        Some(Operation::ADDR),
+        Some(Operation::ADDI),
+        Some(Operation::GTRI),
+    ]);
+    let numeric = encode(&program, &map).unwrap();
+    assert_eq!(
+        "#ip 1\n0 5 0 3\n1 3 0 0\n2 3 -1 3\n3 3 0 2\n1 2 1 1\n0 0 0 1\n",
+        numeric
+    );
+    assert_eq!(program, decode(&numeric, &map).unwrap());
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(
+        Err(Error::Syntax {
+            line: 2,
+            message: String::from("unknown operation: nope"),
+        }),
+        assemble("#ip 0\nnope 1 2 3")
+    );
+    assert_eq!(
+        Err(Error::Syntax {
+            line: 1,
+            message: String::from("invalid operand: missing"),
+        }),
+        assemble("seti missing 0 0")
+    );
+    let map = OpcodeMap::new(vec![Some(Operation::SETI)]);
+    assert_eq!(Err(Error::UnknownOpcode(3)), decode("3 0 0 0", &map));
+    let program = assemble("addr 0 0 0").unwrap();
+    assert_eq!(
+        Err(Error::UnmappedOperation(Operation::ADDR)),
+        encode(&program, &map)
+    );
+}
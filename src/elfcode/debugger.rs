@@ -0,0 +1,450 @@
+use crate::day16::{Device, DeviceError, Program};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// Condition on a register that pauses execution.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Watch {
+    /// Break when the register changes its value.
+    Changed(usize),
+    /// Break when the register takes the given value.
+    Equals(usize, i64),
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Changed(r) => write!(f, "r{}", r),
+            Watch::Equals(r, v) => write!(f, "r{} == {}", r, v),
+        }
+    }
+}
+
+/// Reason why execution paused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stop {
+    /// Reached the instruction with a breakpoint.
+    Breakpoint(usize),
+    /// A watch condition triggered.
+    Watch { watch: Watch, old: i64, new: i64 },
+    /// Executed the requested number of steps.
+    Stepped,
+    /// The program halted.
+    Halted,
+    /// The device failed to execute an instruction.
+    Fault(DeviceError),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(ip) => write!(f, "breakpoint at {}", ip),
+            Stop::Watch { watch, old, new } => write!(f, "watch {}: {} -> {}", watch, old, new),
+            Stop::Stepped => write!(f, "stepped"),
+            Stop::Halted => write!(f, "halted"),
+            Stop::Fault(e) => write!(f, "fault: {}", e),
+        }
+    }
+}
+
+/// Interactive debugger command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Break(usize),
+    Delete(usize),
+    Watch(Watch),
+    Unwatch(usize),
+    Step(usize),
+    Continue,
+    Registers,
+    Set(usize, i64),
+    List,
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let number = |i: usize| -> Result<usize, String> {
+            let w = words.get(i).ok_or("missing argument")?;
+            w.parse().map_err(|_| format!("invalid number: {}", w))
+        };
+        let register = |i: usize| -> Result<usize, String> {
+            let w = words.get(i).ok_or("missing register")?;
+            w.trim_start_matches('r')
+                .parse()
+                .map_err(|_| format!("invalid register: {}", w))
+        };
+        let value = |i: usize| -> Result<i64, String> {
+            let w = words.get(i).ok_or("missing value")?;
+            w.parse().map_err(|_| format!("invalid value: {}", w))
+        };
+        match words.first().cloned().unwrap_or("") {
+            "b" | "break" => Ok(Command::Break(number(1)?)),
+            "d" | "delete" => Ok(Command::Delete(number(1)?)),
+            "w" | "watch" => match words.get(2) {
+                None => Ok(Command::Watch(Watch::Changed(register(1)?))),
+                Some(&"==") => Ok(Command::Watch(Watch::Equals(register(1)?, value(3)?))),
+                Some(w) => Err(format!("unknown condition: {}", w)),
+            },
+            "u" | "unwatch" => Ok(Command::Unwatch(number(1)?)),
+            "s" | "step" => Ok(Command::Step(if words.len() > 1 { number(1)? } else { 1 })),
+            "c" | "continue" => Ok(Command::Continue),
+            "r" | "registers" => Ok(Command::Registers),
+            "set" => Ok(Command::Set(register(1)?, value(2)?)),
+            "l" | "list" => Ok(Command::List),
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            w => Err(format!("unknown command: {}", w)),
+        }
+    }
+}
+
+const HELP: &str = "break N        pause before executing instruction N
+delete N       remove the breakpoint at instruction N
+watch rN       pause when register N changes
+watch rN == V  pause when register N becomes V
+unwatch I      remove watch number I
+step [N]       execute N instructions (default 1)
+continue       run until a breakpoint, a watch or the end
+registers      show the registers
+set rN V       set register N to V
+list           show the program around the current instruction
+quit           exit the debugger";
+
+/// Debugger for Elf-code programs running on a device.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day16::Device;
+/// use aoc18::elfcode::assemble;
+/// use aoc18::elfcode::debugger::{Debugger, Stop, Watch};
+///
+/// let program = assemble("#ip 3
+/// addi 0 1 0
+/// gtri 0 4 1
+/// addr 1 3 3
+/// seti -1 0 3").unwrap();
+/// let mut debugger = Debugger::new(program, Device::new(4));
+/// debugger.add_breakpoint(2);
+/// assert_eq!(Stop::Breakpoint(2), debugger.cont());
+/// assert_eq!(1, debugger.device().registers[0]);
+/// debugger.remove_breakpoint(2);
+/// debugger.add_watch(Watch::Equals(0, 3)).unwrap();
+/// assert_eq!(Stop::Watch { watch: Watch::Equals(0, 3), old: 2, new: 3 }, debugger.cont());
+/// assert_eq!(Stop::Halted, debugger.cont());
+/// assert_eq!(5, debugger.device().registers[0]);
+/// ```
+pub struct Debugger {
+    program: Program,
+    device: Device,
+    ip: Option<usize>,
+    steps: usize,
+    breakpoints: BTreeSet<usize>,
+    watches: Vec<Watch>,
+    /// Whether `cont` already checked for a breakpoint on the first
+    /// instruction.
+    checked_first: bool,
+}
+
+impl Debugger {
+    /// Create a debugger paused before the first instruction.
+    pub fn new(program: Program, device: Device) -> Self {
+        let ip = if program.instructions.is_empty() {
+            None
+        } else {
+            Some(0)
+        };
+        Debugger {
+            program,
+            device,
+            ip,
+            steps: 0,
+            breakpoints: BTreeSet::new(),
+            watches: vec![],
+            checked_first: false,
+        }
+    }
+
+    /// Current state of the device.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Index of the next instruction, or `None` if the program halted.
+    pub fn ip(&self) -> Option<usize> {
+        self.ip
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn add_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.insert(ip);
+    }
+
+    /// Remove a breakpoint, returning whether it existed.
+    pub fn remove_breakpoint(&mut self, ip: usize) -> bool {
+        self.breakpoints.remove(&ip)
+    }
+
+    /// Add a watch, unless it is on a register that the device does not have.
+    pub fn add_watch(&mut self, watch: Watch) -> Result<(), DeviceError> {
+        let r = match watch {
+            Watch::Changed(r) | Watch::Equals(r, _) => r,
+        };
+        if r >= self.device.registers.len() {
+            return Err(DeviceError::InvalidRegister {
+                register: r as i64,
+                count: self.device.registers.len(),
+            });
+        }
+        self.watches.push(watch);
+        Ok(())
+    }
+
+    /// Remove the watch at the given index in the order they were added.
+    pub fn remove_watch(&mut self, index: usize) -> Option<Watch> {
+        if index < self.watches.len() {
+            Some(self.watches.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Execute a single instruction, returning why execution paused, if it
+    /// did.
+    pub fn step(&mut self) -> Option<Stop> {
+        let ip = match self.ip {
+            Some(ip) => ip,
+            None => return Some(Stop::Halted),
+        };
+        let (device, next) = match self.program.step(&self.device, ip) {
+            Ok(r) => r,
+            Err(e) => return Some(Stop::Fault(e)),
+        };
+        let old = std::mem::replace(&mut self.device, device);
+        self.ip = next;
+        self.steps += 1;
+        // Show the device the way the next instruction will see it.
+        if let (Some(r), Some(next)) = (self.program.ip, next) {
+            self.device.registers[r] = next as i64;
+        }
+        for w in &self.watches {
+            let r = match w {
+                Watch::Changed(r) | Watch::Equals(r, _) => *r,
+            };
+            let (old, new) = match (old.registers.get(r), self.device.registers.get(r)) {
+                (Some(&o), Some(&n)) => (o, n),
+                _ => continue,
+            };
+            let triggered = match w {
+                Watch::Changed(_) => old != new,
+                Watch::Equals(_, v) => old != new && new == *v,
+            };
+            if triggered {
+                return Some(Stop::Watch {
+                    watch: w.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+        if self.ip.is_none() {
+            return Some(Stop::Halted);
+        }
+        None
+    }
+
+    /// Run until a breakpoint or watch triggers, or the program halts.
+    pub fn cont(&mut self) -> Stop {
+        if self.steps == 0 && !self.checked_first {
+            self.checked_first = true;
+            if let Some(ip) = self.ip.filter(|ip| self.breakpoints.contains(ip)) {
+                return Stop::Breakpoint(ip);
+            }
+        }
+        loop {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+            if let Some(ip) = self.ip {
+                if self.breakpoints.contains(&ip) {
+                    return Stop::Breakpoint(ip);
+                }
+            }
+        }
+    }
+
+    /// Apply an interactive command, returning the text to show.
+    pub fn apply(&mut self, command: &Command) -> String {
+        match command {
+            Command::Break(ip) => {
+                self.add_breakpoint(*ip);
+                format!("breakpoint at {}", ip)
+            }
+            Command::Delete(ip) => {
+                if self.remove_breakpoint(*ip) {
+                    format!("deleted breakpoint at {}", ip)
+                } else {
+                    format!("no breakpoint at {}", ip)
+                }
+            }
+            Command::Watch(w) => match self.add_watch(w.clone()) {
+                Ok(()) => format!("watch {}: {}", self.watches.len() - 1, w),
+                Err(e) => e.to_string(),
+            },
+            Command::Unwatch(i) => match self.remove_watch(*i) {
+                Some(w) => format!("deleted watch {}", w),
+                None => format!("no watch {}", i),
+            },
+            Command::Step(n) => {
+                let mut stop = Stop::Stepped;
+                for _ in 0..*n {
+                    if let Some(s) = self.step() {
+                        stop = s;
+                        break;
+                    }
+                }
+                format!("{}\n{}", stop, self.status())
+            }
+            Command::Continue => {
+                let stop = self.cont();
+                format!("{}\n{}", stop, self.status())
+            }
+            Command::Registers => self.status(),
+            Command::Set(r, v) => match self.device.set_register(*r as i64, *v) {
+                Ok(()) => self.status(),
+                Err(e) => e.to_string(),
+            },
+            Command::List => self.listing(),
+            Command::Help => String::from(HELP),
+            Command::Quit => String::new(),
+        }
+    }
+
+    fn status(&self) -> String {
+        let regs: Vec<String> = self
+            .device
+            .registers
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        match self.ip {
+            Some(ip) => format!(
+                "steps={} ip={} [{}] {}",
+                self.steps,
+                ip,
+                regs.join(", "),
+                self.program.instructions[ip]
+            ),
+            None => format!("steps={} halted [{}]", self.steps, regs.join(", ")),
+        }
+    }
+
+    fn listing(&self) -> String {
+        let current = self.ip.unwrap_or(usize::MAX);
+        let from = current
+            .saturating_sub(5)
+            .min(self.program.instructions.len());
+        self.program
+            .instructions
+            .iter()
+            .enumerate()
+            .skip(from)
+            .take(11)
+            .map(|(i, ins)| {
+                let marker = match (i == current, self.breakpoints.contains(&i)) {
+                    (true, _) => "=>",
+                    (false, true) => " *",
+                    (false, false) => "  ",
+                };
+                format!("{} {:3}: {}", marker, i, ins)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!(Ok(Command::Break(17)), Command::from_str("break 17"));
+    assert_eq!(Ok(Command::Step(1)), Command::from_str("s"));
+    assert_eq!(Ok(Command::Step(10)), Command::from_str("step 10"));
+    assert_eq!(
+        Ok(Command::Watch(Watch::Changed(0))),
+        Command::from_str("watch r0")
+    );
+    assert_eq!(
+        Ok(Command::Watch(Watch::Equals(3, -2))),
+        Command::from_str("w r3 == -2")
+    );
+    assert_eq!(Ok(Command::Set(0, 1)), Command::from_str("set r0 1"));
+    assert!(Command::from_str("break").is_err());
+    assert!(Command::from_str("jump 3").is_err());
+}
+
+#[test]
+fn test_step_and_watch_changed() {
+    let program = crate::elfcode::assemble(
+        "seti 5 0 1
+addi 1 1 1
+seti 9 0 0",
+    )
+    .unwrap();
+    let mut debugger = Debugger::new(program, Device::new(2));
+    debugger.add_watch(Watch::Changed(0)).unwrap();
+    assert_eq!(None, debugger.step());
+    assert_eq!(Some(1), debugger.ip());
+    assert_eq!(
+        Stop::Watch {
+            watch: Watch::Changed(0),
+            old: 0,
+            new: 9
+        },
+        debugger.cont()
+    );
+    assert_eq!(None, debugger.ip());
+    assert_eq!(3, debugger.steps());
+    assert_eq!(Some(Stop::Halted), debugger.step());
+}
+
+#[test]
+fn test_watch_invalid_register() {
+    let program = crate::elfcode::assemble("seti 5 0 1").unwrap();
+    let mut debugger = Debugger::new(program, Device::new(6));
+    assert_eq!(
+        Err(DeviceError::InvalidRegister {
+            register: 9,
+            count: 6
+        }),
+        debugger.add_watch(Watch::Changed(9))
+    );
+    assert_eq!(
+        "invalid register 9 for a device with 6 registers",
+        debugger.apply(&Command::from_str("watch r9").unwrap())
+    );
+    assert_eq!("no watch 0", debugger.apply(&Command::Unwatch(0)));
+}
+
+#[test]
+fn test_breakpoint_on_first_instruction() {
+    let program = crate::elfcode::assemble(
+        "seti 5 0 1
+seti 9 0 0",
+    )
+    .unwrap();
+    let mut debugger = Debugger::new(program, Device::new(2));
+    assert_eq!("breakpoint at 0", debugger.apply(&Command::Break(0)));
+    assert_eq!(Stop::Breakpoint(0), debugger.cont());
+    assert_eq!(0, debugger.steps());
+    assert_eq!(Stop::Halted, debugger.cont());
+    assert_eq!(2, debugger.steps());
+}
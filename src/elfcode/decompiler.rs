@@ -0,0 +1,611 @@
+use crate::day16::{Device, Instruction, Operation, Program};
+use petgraph::dot::Dot;
+use petgraph::graph::Graph;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// A straight-line run of instructions that is only entered at its first
+/// instruction and only left after its last one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// Index of the first instruction.
+    pub start: usize,
+    /// The instructions in the block.
+    pub instructions: Vec<Instruction>,
+}
+
+impl Block {
+    fn exit() -> Self {
+        Block {
+            start: usize::MAX,
+            instructions: vec![],
+        }
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == usize::MAX {
+            return write!(f, "halt");
+        }
+        for (i, ins) in self.instructions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", self.start + i, ins)?;
+        }
+        Ok(())
+    }
+}
+
+/// How control passes from one block to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Branch {
+    /// Execution falls through to the next instruction.
+    Next,
+    /// Unconditional jump.
+    Jump,
+    /// Conditional jump when the condition holds.
+    Taken,
+    /// Conditional jump when the condition does not hold.
+    NotTaken,
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Branch::Next => "",
+            Branch::Jump => "jump",
+            Branch::Taken => "true",
+            Branch::NotTaken => "false",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Build the control-flow graph of the program. Jumps whose target cannot be
+/// determined statically have no outgoing edges; leaving the program leads to
+/// a single `halt` node.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::elfcode::assemble;
+/// use aoc18::elfcode::decompiler::control_flow_graph;
+///
+/// let program = assemble("#ip 3
+/// addi 0 1 0
+/// gtri 0 4 1
+/// addr 1 3 3
+/// seti -1 0 3").unwrap();
+/// let cfg = control_flow_graph(&program);
+/// assert_eq!(3, cfg.node_count());
+/// assert_eq!(3, cfg.edge_count());
+/// ```
+pub fn control_flow_graph(program: &Program) -> Graph<Block, Branch> {
+    let flows = flows(program);
+    let n = program.instructions.len();
+
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    for (i, flow) in flows.iter().enumerate() {
+        if *flow != Flow::Next {
+            leaders.insert(i + 1);
+            for (t, _) in flow.successors(i) {
+                leaders.insert(t);
+            }
+        }
+    }
+    let leaders: Vec<usize> = leaders.into_iter().filter(|&l| l < n).collect();
+
+    let mut graph = Graph::new();
+    let mut nodes = HashMap::new();
+    for (k, &start) in leaders.iter().enumerate() {
+        let end = leaders.get(k + 1).cloned().unwrap_or(n);
+        let node = graph.add_node(Block {
+            start,
+            instructions: program.instructions[start..end].to_vec(),
+        });
+        nodes.insert(start, (node, end - 1));
+    }
+    let mut exit = None;
+    for &start in &leaders {
+        let (node, last) = nodes[&start];
+        for (t, branch) in flows[last].successors(last) {
+            let target = if t < n {
+                nodes[&t].0
+            } else {
+                *exit.get_or_insert_with(|| graph.add_node(Block::exit()))
+            };
+            graph.add_edge(node, target, branch);
+        }
+    }
+    graph
+}
+
+/// Render the control-flow graph in the DOT language of Graphviz.
+pub fn to_dot(graph: &Graph<Block, Branch>) -> String {
+    format!("{}", Dot::new(graph))
+}
+
+/// Decompile the program into structured pseudo-code.
+///
+/// Comparisons that only feed a conditional jump are folded into the jump.
+/// Backward jumps become `loop` or `do`/`while` loops, forward conditional
+/// jumps become `if` blocks, and anything that does not fit falls back to
+/// labels and `goto`. Reading the instruction pointer register yields the
+/// address of the instruction itself.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::elfcode::assemble;
+/// use aoc18::elfcode::decompiler::pseudo_code;
+///
+/// let program = assemble("#ip 3
+/// seti 0 0 0
+/// addi 0 1 0
+/// gtri 0 4 1
+/// addr 1 3 3
+/// seti 0 0 3").unwrap();
+/// assert_eq!("r0 = 0;
+/// do {
+///     r0 += 1;
+/// } while (r0 <= 4);
+/// ", pseudo_code(&program));
+/// ```
+pub fn pseudo_code(program: &Program) -> String {
+    let decompiler = Decompiler::new(program);
+    let mut lines = vec![];
+    let mut targets = BTreeSet::new();
+    decompiler.emit(0, decompiler.stmts.len(), 0, None, &mut lines, &mut targets);
+
+    let mut result = String::new();
+    for (addr, depth, text) in lines {
+        if targets.remove(&addr) {
+            result += &format!("L{}:\n", addr);
+        }
+        result += &format!("{}{}\n", "    ".repeat(depth), text);
+    }
+    result
+}
+
+/// How control leaves a single instruction.
+#[derive(Clone, Debug, PartialEq)]
+enum Flow {
+    Next,
+    /// Jump to the given address.
+    Jump(i64),
+    /// Skip the next instruction when the comparison at the previous address
+    /// holds.
+    Branch,
+    Computed,
+}
+
+impl Flow {
+    fn successors(&self, i: usize) -> Vec<(usize, Branch)> {
+        let addr = |t: i64| if t < 0 { usize::MAX } else { t as usize };
+        match self {
+            Flow::Next => vec![(i + 1, Branch::Next)],
+            Flow::Jump(t) => vec![(addr(*t), Branch::Jump)],
+            Flow::Branch => vec![(i + 1, Branch::NotTaken), (i + 2, Branch::Taken)],
+            Flow::Computed => vec![],
+        }
+    }
+}
+
+fn is_comparison(op: &Operation) -> bool {
+    matches!(
+        op,
+        Operation::GTIR
+            | Operation::GTRI
+            | Operation::GTRR
+            | Operation::EQIR
+            | Operation::EQRI
+            | Operation::EQRR
+    )
+}
+
+/// Find how control leaves every instruction.
+fn flows(program: &Program) -> Vec<Flow> {
+    let ip = match program.ip {
+        Some(ip) => ip as i64,
+        None => return vec![Flow::Next; program.instructions.len()],
+    };
+    let targets_of = |flows: &[Flow]| -> BTreeSet<usize> {
+        flows
+            .iter()
+            .enumerate()
+            .flat_map(|(i, f)| f.successors(i))
+            .filter(|&(t, b)| b != Branch::Next && b != Branch::NotTaken && t != usize::MAX)
+            .map(|(t, _)| t)
+            .collect()
+    };
+
+    // Constant jumps first, so that conditional jumps can check that the
+    // comparison before them is always executed right before.
+    let mut result: Vec<Flow> = program
+        .instructions
+        .iter()
+        .enumerate()
+        .map(|(i, ins)| {
+            if ins.c != ip {
+                return Flow::Next;
+            }
+            let (ra, rb) = ins.op.register_operands();
+            if (ra && ins.a != ip) || (rb && ins.b != ip) {
+                return Flow::Computed;
+            }
+            let mut device = Device::new(ip as usize + 1);
+            device.registers[ip as usize] = i as i64;
+            match device.execute(ins) {
+                Ok(d) => Flow::Jump(d.registers[ip as usize] + 1),
+                Err(_) => Flow::Computed,
+            }
+        })
+        .collect();
+    let targets = targets_of(&result);
+    for i in 1..result.len() {
+        let ins = &program.instructions[i];
+        let prev = &program.instructions[i - 1];
+        if result[i] == Flow::Computed
+            && ins.op == Operation::ADDR
+            && (ins.a == ip || ins.b == ip)
+            && is_comparison(&prev.op)
+            && prev.c != ip
+            && (ins.a == prev.c || ins.b == prev.c)
+            && !targets.contains(&i)
+            && result[i - 1] == Flow::Next
+        {
+            result[i] = Flow::Branch;
+        }
+    }
+    result
+}
+
+/// A condition of a conditional jump.
+#[derive(Clone, Debug, PartialEq)]
+struct Cond {
+    lhs: String,
+    op: &'static str,
+    rhs: String,
+}
+
+impl Cond {
+    fn negate(&self) -> Self {
+        let op = match self.op {
+            ">" => "<=",
+            "<=" => ">",
+            "==" => "!=",
+            _ => "==",
+        };
+        Cond {
+            lhs: self.lhs.clone(),
+            op,
+            rhs: self.rhs.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op, self.rhs)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Assign(String),
+    Goto(i64),
+    /// Jump to the address when the condition holds.
+    Branch(Cond, i64),
+    Computed(String),
+}
+
+/// A statement covering the instructions from `addr` up to `end`.
+#[derive(Clone, Debug, PartialEq)]
+struct Stmt {
+    addr: usize,
+    end: usize,
+    kind: Kind,
+}
+
+/// The innermost loop around the statements being emitted.
+#[derive(Clone, Copy)]
+struct Loop {
+    header: usize,
+    exit: usize,
+    /// Whether a jump to the header can be written as `continue`.
+    can_continue: bool,
+}
+
+struct Decompiler {
+    len: usize,
+    stmts: Vec<Stmt>,
+}
+
+impl Decompiler {
+    fn new(program: &Program) -> Self {
+        let flows = flows(program);
+        let ip = program.ip.map(|r| r as i64);
+        let operand = |v: i64, register: bool, at: usize| {
+            if !register {
+                v.to_string()
+            } else if Some(v) == ip {
+                at.to_string()
+            } else {
+                format!("r{}", v)
+            }
+        };
+        let expr = |ins: &Instruction, at: usize| -> (String, String) {
+            let (ra, rb) = ins.op.register_operands();
+            (operand(ins.a, ra, at), operand(ins.b, rb, at))
+        };
+        let cond = |ins: &Instruction, at: usize| {
+            let (lhs, rhs) = expr(ins, at);
+            let op = match ins.op {
+                Operation::GTIR | Operation::GTRI | Operation::GTRR => ">",
+                _ => "==",
+            };
+            Cond { lhs, op, rhs }
+        };
+
+        let mut targets = BTreeSet::new();
+        for (i, f) in flows.iter().enumerate() {
+            for (t, b) in f.successors(i) {
+                if b != Branch::Next && b != Branch::NotTaken {
+                    targets.insert(t);
+                }
+            }
+        }
+
+        let instructions = &program.instructions;
+        let mut stmts: Vec<Stmt> = vec![];
+        let mut i = 0;
+        while i < instructions.len() {
+            let ins = &instructions[i];
+            // A comparison followed by a conditional skip, optionally over a
+            // jump, becomes a single conditional jump.
+            if flows.get(i + 1) == Some(&Flow::Branch) {
+                let c = cond(ins, i);
+                if let (Some(Flow::Jump(t)), false) = (flows.get(i + 2), targets.contains(&(i + 2)))
+                {
+                    stmts.push(Stmt {
+                        addr: i,
+                        end: i + 3,
+                        kind: Kind::Branch(c.negate(), *t),
+                    });
+                    i += 3;
+                } else {
+                    stmts.push(Stmt {
+                        addr: i,
+                        end: i + 2,
+                        kind: Kind::Branch(c, i as i64 + 3),
+                    });
+                    i += 2;
+                }
+                continue;
+            }
+            let kind = match &flows[i] {
+                Flow::Jump(t) => Kind::Goto(*t),
+                Flow::Computed => {
+                    let (a, b) = expr(ins, i);
+                    Kind::Computed(Decompiler::value(&ins.op, &a, &b))
+                }
+                _ => {
+                    let (a, b) = expr(ins, i);
+                    let dst = format!("r{}", ins.c);
+                    let compound = match ins.op {
+                        Operation::ADDR | Operation::ADDI => Some("+="),
+                        Operation::MULR | Operation::MULI => Some("*="),
+                        Operation::BANR | Operation::BANI => Some("&="),
+                        Operation::BORR | Operation::BORI => Some("|="),
+                        _ => None,
+                    };
+                    Kind::Assign(match compound {
+                        Some(op) if a == dst => format!("{} {} {};", dst, op, b),
+                        Some(op) if b == dst => format!("{} {} {};", dst, op, a),
+                        _ => format!("{} = {};", dst, Decompiler::value(&ins.op, &a, &b)),
+                    })
+                }
+            };
+            stmts.push(Stmt {
+                addr: i,
+                end: i + 1,
+                kind,
+            });
+            i += 1;
+        }
+        Decompiler {
+            len: instructions.len(),
+            stmts,
+        }
+    }
+
+    fn value(op: &Operation, a: &str, b: &str) -> String {
+        match op {
+            Operation::ADDR | Operation::ADDI => format!("{} + {}", a, b),
+            Operation::MULR | Operation::MULI => format!("{} * {}", a, b),
+            Operation::BANR | Operation::BANI => format!("{} & {}", a, b),
+            Operation::BORR | Operation::BORI => format!("{} | {}", a, b),
+            Operation::SETR | Operation::SETI => a.to_string(),
+            Operation::GTIR | Operation::GTRI | Operation::GTRR => format!("({} > {})", a, b),
+            Operation::EQIR | Operation::EQRI | Operation::EQRR => format!("({} == {})", a, b),
+            Operation::OP(o) => format!("op{}({}, {})", o, a, b),
+        }
+    }
+
+    /// Address of the statement at the given index, or the end of the
+    /// program.
+    fn addr(&self, k: usize) -> usize {
+        self.stmts.get(k).map(|s| s.addr).unwrap_or(self.len)
+    }
+
+    fn jump(&self, target: i64, ctx: Option<Loop>, targets: &mut BTreeSet<usize>) -> String {
+        if target < 0 || target as usize >= self.len {
+            return String::from("halt;");
+        }
+        let target = target as usize;
+        match ctx {
+            Some(l) if l.exit == target => String::from("break;"),
+            Some(l) if l.can_continue && l.header == target => String::from("continue;"),
+            _ => {
+                targets.insert(target);
+                format!("goto L{};", target)
+            }
+        }
+    }
+
+    fn emit(
+        &self,
+        from: usize,
+        to: usize,
+        depth: usize,
+        ctx: Option<Loop>,
+        lines: &mut Vec<(usize, usize, String)>,
+        targets: &mut BTreeSet<usize>,
+    ) {
+        let mut k = from;
+        while k < to {
+            let s = &self.stmts[k];
+            let back_jump = (k..to).rev().find(|&t| match &self.stmts[t].kind {
+                Kind::Goto(h) | Kind::Branch(_, h) => *h == s.addr as i64,
+                _ => false,
+            });
+            if let Some(t) = back_jump {
+                let closing = &self.stmts[t];
+                let exit = closing.end;
+                match &closing.kind {
+                    Kind::Branch(c, _) => {
+                        lines.push((s.addr, depth, String::from("do {")));
+                        let inner = Loop {
+                            header: s.addr,
+                            exit,
+                            can_continue: false,
+                        };
+                        self.emit(k, t, depth + 1, Some(inner), lines, targets);
+                        lines.push((closing.addr, depth, format!("}} while ({});", c)));
+                    }
+                    _ => {
+                        lines.push((s.addr, depth, String::from("loop {")));
+                        let inner = Loop {
+                            header: s.addr,
+                            exit,
+                            can_continue: true,
+                        };
+                        self.emit(k, t, depth + 1, Some(inner), lines, targets);
+                        lines.push((closing.addr, depth, String::from("}")));
+                    }
+                }
+                k = t + 1;
+                continue;
+            }
+
+            match &s.kind {
+                Kind::Assign(text) => lines.push((s.addr, depth, text.clone())),
+                Kind::Computed(e) => {
+                    lines.push((s.addr, depth, format!("goto *({} + 1);", e)));
+                }
+                Kind::Goto(t) => {
+                    let text = self.jump(*t, ctx, targets);
+                    lines.push((s.addr, depth, text));
+                }
+                Kind::Branch(c, t) => {
+                    let end = (k + 1..=to).find(|&j| self.addr(j) as i64 == *t);
+                    let is_loop_jump = match ctx {
+                        Some(l) => l.exit as i64 == *t || (l.can_continue && l.header as i64 == *t),
+                        None => false,
+                    };
+                    match end {
+                        Some(j) if !is_loop_jump && j > k + 1 => {
+                            lines.push((s.addr, depth, format!("if ({}) {{", c.negate())));
+                            self.emit(k + 1, j, depth + 1, ctx, lines, targets);
+                            lines.push((usize::MAX, depth, String::from("}")));
+                            k = j;
+                            continue;
+                        }
+                        _ => {
+                            let text = format!("if ({}) {}", c, self.jump(*t, ctx, targets));
+                            lines.push((s.addr, depth, text));
+                        }
+                    }
+                }
+            }
+            k += 1;
+        }
+    }
+}
+
+#[test]
+fn test_pseudo_code_nested_loops() {
+    // Sum of the divisors of r5, in the shape of the day 19 program.
+    let program = crate::elfcode::assemble(
+        "#ip 3
+seti 1 0 4
+seti 1 7 2
+mulr 4 2 1
+eqrr 1 5 1
+addr 1 3 3
+addi 3 1 3
+addr 4 0 0
+addi 2 1 2
+gtrr 2 5 1
+addr 3 1 3
+seti 1 3 3
+addi 4 1 4
+gtrr 4 5 1
+addr 1 3 3
+seti 0 6 3
+mulr 3 3 3",
+    )
+    .unwrap();
+    assert_eq!(
+        "r4 = 1;
+do {
+    r2 = 1;
+    do {
+        r1 = r4 * r2;
+        if (r1 == r5) {
+            r0 += r4;
+        }
+        r2 += 1;
+    } while (r2 <= r5);
+    r4 += 1;
+} while (r4 <= r5);
+halt;
+",
+        pseudo_code(&program)
+    );
+    let cfg = control_flow_graph(&program);
+    // Ten blocks and the halt node.
+    assert_eq!(11, cfg.node_count());
+    assert!(to_dot(&cfg).starts_with("digraph {"));
+}
+
+#[test]
+fn test_pseudo_code_goto() {
+    let program = crate::elfcode::assemble(
+        "#ip 4
+seti 5 0 4
+addi 0 1 0
+eqri 0 3 1
+addr 1 4 4
+seti 0 0 4
+seti 99 0 4
+addr 2 4 4
+seti 7 0 0",
+    )
+    .unwrap();
+    assert_eq!(
+        "goto L6;
+do {
+    r0 += 1;
+} while (r0 != 3);
+halt;
+L6:
+goto *(r2 + 6 + 1);
+r0 = 7;
+",
+        pseudo_code(&program)
+    );
+}
@@ -0,0 +1,344 @@
+use crate::day16::{Instruction, Operation, Program};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// A loop that is known to compute something simple.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// `do { c += 1 } while (c <= n)`, leaving `c` at `max(c, n) + 1`.
+    CountUp,
+    /// Division by counting as in day 21: find the smallest `t >= 0` with
+    /// `(t + 1) * k > n`.
+    Divide,
+    /// The sum of divisors as in day 19: add every `i` of `1..=n` that has a
+    /// `j` of `1..=n` with `i * j == n` to a register.
+    DivisorSum,
+}
+
+/// Instructions that make up every kind of loop. Upper case letters stand
+/// for distinct registers other than the instruction pointer, lower case
+/// letters for values, `ip` for the instruction pointer register, `_` for
+/// anything and `@k` for the value that jumps to the k-th instruction of the
+/// loop. The operands of commutative operations may appear in either order.
+const TEMPLATES: &[(Kind, &str)] = &[
+    (
+        Kind::CountUp,
+        "addi C 1 C
+        gtrr C N T
+        addr T ip ip
+        seti @0 _ ip",
+    ),
+    (
+        Kind::CountUp,
+        "addi C 1 C
+        gtri C n T
+        addr T ip ip
+        seti @0 _ ip",
+    ),
+    (
+        Kind::Divide,
+        "seti 0 _ T
+        addi T 1 U
+        muli U k U
+        gtrr U N U
+        addr U ip ip
+        addi ip 1 ip
+        seti @9 _ ip
+        addi T 1 T
+        seti @1 _ ip",
+    ),
+    (
+        Kind::DivisorSum,
+        "seti 1 _ I
+        seti 1 _ J
+        mulr I J T
+        eqrr T N T
+        addr T ip ip
+        addi ip 1 ip
+        addr I S S
+        addi J 1 J
+        gtrr J N T
+        addr ip T ip
+        seti @2 _ ip
+        addi I 1 I
+        gtrr I N T
+        addr T ip ip
+        seti @1 _ ip",
+    ),
+];
+
+/// A loop of a program that can be computed without interpreting it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Idiom {
+    pub kind: Kind,
+    /// Index of the first instruction of the loop.
+    pub start: usize,
+    /// Index of the instruction that follows the loop.
+    pub end: usize,
+    ip: usize,
+    vars: HashMap<char, i64>,
+}
+
+/// Find the loops of the program that can be computed without interpreting
+/// them.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::elfcode::assemble;
+/// use aoc18::elfcode::idiom::{find_idioms, Kind};
+///
+/// let program = assemble("#ip 3
+/// addi 0 1 0
+/// gtri 0 4 1
+/// addr 1 3 3
+/// seti -1 0 3").unwrap();
+/// let idioms = find_idioms(&program);
+/// assert_eq!(1, idioms.len());
+/// assert_eq!((Kind::CountUp, 0, 4), (idioms[0].kind, idioms[0].start, idioms[0].end));
+/// ```
+pub fn find_idioms(program: &Program) -> Vec<Idiom> {
+    let ip = match program.ip {
+        Some(ip) => ip,
+        None => return vec![],
+    };
+    let mut result = vec![];
+    for start in 0..program.instructions.len() {
+        for (kind, template) in TEMPLATES {
+            let template: Vec<&str> = template.split('\n').map(str::trim).collect();
+            let code = &program.instructions[start..];
+            if code.len() < template.len() {
+                continue;
+            }
+            let mut vars = HashMap::new();
+            let matched = template.iter().zip(code).all(|(line, instruction)| {
+                match_line(line, instruction, ip as i64, start as i64, &mut vars)
+            });
+            if matched {
+                result.push(Idiom {
+                    kind: *kind,
+                    start,
+                    end: start + template.len(),
+                    ip,
+                    vars,
+                });
+                break;
+            }
+        }
+    }
+    result
+}
+
+/// Match an instruction against a line of a template, binding its variables.
+fn match_line(
+    line: &str,
+    instruction: &Instruction,
+    ip: i64,
+    start: i64,
+    vars: &mut HashMap<char, i64>,
+) -> bool {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if Operation::from_str(fields[0]) != Ok(instruction.op.clone()) {
+        return false;
+    }
+    let commutative = matches!(
+        instruction.op,
+        Operation::ADDR | Operation::MULR | Operation::BANR | Operation::BORR | Operation::EQRR
+    );
+    let (a, b, c) = (instruction.a, instruction.b, instruction.c);
+    let mut orders = vec![(a, b)];
+    if commutative {
+        orders.push((b, a));
+    }
+    for (a, b) in orders {
+        let mut bound = vars.clone();
+        let all = fields[1..]
+            .iter()
+            .zip(&[a, b, c])
+            .all(|(field, &value)| operand(field, value, ip, start, &mut bound));
+        if all {
+            *vars = bound;
+            return true;
+        }
+    }
+    false
+}
+
+fn operand(field: &str, value: i64, ip: i64, start: i64, vars: &mut HashMap<char, i64>) -> bool {
+    if field == "_" {
+        return true;
+    }
+    if field == "ip" {
+        return value == ip;
+    }
+    if let Some(k) = field.strip_prefix('@') {
+        return k.parse::<i64>().map(|k| value == start + k - 1) == Ok(true);
+    }
+    if let Ok(v) = field.parse::<i64>() {
+        return value == v;
+    }
+    let name = field.chars().next().unwrap();
+    if let Some(&v) = vars.get(&name) {
+        return value == v;
+    }
+    if name.is_ascii_uppercase() {
+        // Registers must be distinct from each other and from the
+        // instruction pointer.
+        let taken = value == ip
+            || vars
+                .iter()
+                .any(|(n, &v)| n.is_ascii_uppercase() && v == value);
+        if taken {
+            return false;
+        }
+    }
+    vars.insert(name, value);
+    true
+}
+
+impl Idiom {
+    /// Compute the loop on the registers as if it had been run from its first
+    /// instruction, returning the number of instructions that it would have
+    /// executed. Afterwards the instruction pointer register points at the
+    /// last instruction of the loop that was executed. Returns `None` without
+    /// touching the registers if the loop would not end, or if values would
+    /// exceed `max` on the way.
+    pub fn apply(&self, registers: &mut [i64], max: i64) -> Option<u64> {
+        let reg = |name: char| self.vars[&name] as usize;
+        let steps = match self.kind {
+            Kind::CountUp => {
+                let n = match self.vars.get(&'n') {
+                    Some(&n) => n,
+                    None => registers[reg('N')],
+                };
+                let c = registers[reg('C')];
+                if n >= max || c >= max {
+                    return None;
+                }
+                // The body runs at least once.
+                let count = n.checked_sub(c)?.max(0) + 1;
+                let steps = (count as u64).checked_mul(4)? - 1;
+                registers[reg('C')] = c + count;
+                registers[reg('T')] = 1;
+                steps
+            }
+            Kind::Divide => {
+                let (k, n) = (self.vars[&'k'], registers[reg('N')]);
+                if k <= 0 || n > max - k {
+                    return None;
+                }
+                let t = if n < 0 { 0 } else { n / k };
+                registers[reg('T')] = t;
+                registers[reg('U')] = 1;
+                (t as u64).checked_mul(7)?.checked_add(6)?
+            }
+            Kind::DivisorSum => {
+                let n = registers[reg('N')];
+                let m = n.max(1);
+                if m.checked_mul(m)? > max || m >= max {
+                    return None;
+                }
+                let mut sum: i64 = 0;
+                let mut i = 1;
+                while i * i <= n {
+                    if n % i == 0 {
+                        sum += i;
+                        if i != n / i {
+                            sum += n / i;
+                        }
+                    }
+                    i += 1;
+                }
+                let s = registers[reg('S')].checked_add(sum)?;
+                if s > max {
+                    return None;
+                }
+                registers[reg('S')] = s;
+                registers[reg('I')] = m + 1;
+                registers[reg('J')] = m + 1;
+                registers[reg('T')] = 1;
+                (m as u64).checked_mul((m as u64).checked_mul(8)?.checked_add(4)?)?
+            }
+        };
+        registers[self.ip] = self.end as i64 - 1;
+        Some(steps)
+    }
+}
+
+/// Run the program with and without idioms for every initial value of
+/// register `r`, checking that the results are the same.
+#[cfg(test)]
+fn cross_check(source: &str, kind: Kind, r: usize, values: std::ops::RangeInclusive<i64>) {
+    use crate::day16::Device;
+    use crate::elfcode::vm::Machine;
+
+    let program = crate::elfcode::assemble(source).unwrap();
+    assert_eq!(Some(kind), find_idioms(&program).first().map(|i| i.kind));
+    for v in values {
+        let mut device = Device::new(6);
+        device.registers[r] = v;
+        let mut plain = Machine::new(&program, device.clone()).unwrap();
+        plain.run();
+        let mut optimized = Machine::optimized(&program, device).unwrap();
+        optimized.run();
+        assert_eq!(plain.registers(), optimized.registers(), "r{} = {}", r, v);
+        assert_eq!(plain.steps(), optimized.steps(), "r{} = {}", r, v);
+    }
+}
+
+#[test]
+fn test_divisor_sum() {
+    cross_check(
+        "#ip 3
+seti 1 0 4
+seti 1 7 2
+mulr 4 2 1
+eqrr 5 1 1
+addr 1 3 3
+addi 3 1 3
+addr 0 4 0
+addi 2 1 2
+gtrr 2 5 1
+addr 3 1 3
+seti 1 3 3
+addi 4 1 4
+gtrr 4 5 1
+addr 1 3 3
+seti 0 6 3
+mulr 3 3 3",
+        Kind::DivisorSum,
+        5,
+        -2..=40,
+    );
+}
+
+#[test]
+fn test_divide() {
+    cross_check(
+        "#ip 1
+seti 0 0 2
+addi 2 1 4
+muli 4 7 4
+gtrr 4 5 4
+addr 4 1 1
+addi 1 1 1
+seti 8 3 1
+addi 2 1 2
+seti 0 8 1",
+        Kind::Divide,
+        5,
+        -3..=60,
+    );
+}
+
+#[test]
+fn test_count_up() {
+    let source = "#ip 0
+addi 2 1 2
+gtrr 2 4 3
+addr 3 0 0
+seti -1 0 0
+addi 2 0 5";
+    cross_check(source, Kind::CountUp, 4, -5..=20);
+    cross_check(source, Kind::CountUp, 2, -5..=20);
+}
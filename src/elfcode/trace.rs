@@ -0,0 +1,271 @@
+use crate::day16::Program;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// File format of a recorded trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A header line, then one line per instruction with its index and the
+    /// registers before and after executing it.
+    Csv,
+    /// The magic bytes `ELFT` and the number of registers as a byte, then per
+    /// instruction its index as a little-endian `u32` followed by the
+    /// registers before and after as little-endian `i64`s.
+    Binary,
+}
+
+const MAGIC: &[u8] = b"ELFT";
+
+/// A single executed instruction in a trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub ip: usize,
+    pub before: Vec<i64>,
+    pub after: Vec<i64>,
+}
+
+/// Writes a trace of executed instructions.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day16::Device;
+/// use aoc18::elfcode::assemble;
+/// use aoc18::elfcode::trace::{Format, Recorder};
+/// use aoc18::elfcode::vm::Machine;
+///
+/// let program = assemble("seti 7 0 0\naddi 0 1 1").unwrap();
+/// let mut recorder = Recorder::new(vec![], Format::Csv, 2).unwrap();
+/// let mut machine = Machine::new(&program, Device::new(2)).unwrap();
+/// machine.run_traced(|ip, before, after| recorder.record(ip, before, after).is_ok());
+/// assert_eq!("ip,before0,before1,after0,after1
+/// 0,0,0,7,0
+/// 1,7,0,7,8
+/// ", String::from_utf8(recorder.finish().unwrap()).unwrap());
+/// ```
+pub struct Recorder<W: Write> {
+    out: W,
+    format: Format,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Start a trace of a device with the given number of registers.
+    pub fn new(mut out: W, format: Format, registers: usize) -> io::Result<Self> {
+        match format {
+            Format::Csv => {
+                let mut header = vec![String::from("ip")];
+                for prefix in &["before", "after"] {
+                    header.extend((0..registers).map(|r| format!("{}{}", prefix, r)));
+                }
+                writeln!(out, "{}", header.join(","))?;
+            }
+            Format::Binary => {
+                if registers > u8::MAX as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "too many registers for a binary trace",
+                    ));
+                }
+                out.write_all(MAGIC)?;
+                out.write_all(&[registers as u8])?;
+            }
+        }
+        Ok(Recorder { out, format })
+    }
+
+    /// Record an executed instruction.
+    pub fn record(&mut self, ip: usize, before: &[i64], after: &[i64]) -> io::Result<()> {
+        match self.format {
+            Format::Csv => {
+                let mut line = ip.to_string();
+                for r in before.iter().chain(after) {
+                    line += &format!(",{}", r);
+                }
+                writeln!(self.out, "{}", line)
+            }
+            Format::Binary => {
+                self.out.write_all(&(ip as u32).to_le_bytes())?;
+                for r in before.iter().chain(after) {
+                    self.out.write_all(&r.to_le_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Flush the trace and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Read a trace written in the binary format.
+pub fn read_binary<R: Read>(mut input: R) -> io::Result<Vec<Entry>> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut header = [0; 5];
+    input.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a binary trace"));
+    }
+    let registers = header[4] as usize;
+
+    let mut data = vec![];
+    input.read_to_end(&mut data)?;
+    let size = 4 + 16 * registers;
+    if data.len() % size != 0 {
+        return Err(invalid("truncated trace"));
+    }
+    let value = |bytes: &[u8]| {
+        let mut b = [0; 8];
+        b.copy_from_slice(bytes);
+        i64::from_le_bytes(b)
+    };
+    Ok(data
+        .chunks(size)
+        .map(|chunk| {
+            let mut ip = [0; 4];
+            ip.copy_from_slice(&chunk[..4]);
+            let values: Vec<i64> = chunk[4..].chunks(8).map(value).collect();
+            Entry {
+                ip: u32::from_le_bytes(ip) as usize,
+                before: values[..registers].to_vec(),
+                after: values[registers..].to_vec(),
+            }
+        })
+        .collect())
+}
+
+/// Number of times every instruction of a program was executed.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day16::Device;
+/// use aoc18::elfcode::assemble;
+/// use aoc18::elfcode::trace::Profile;
+/// use aoc18::elfcode::vm::Machine;
+///
+/// let program = assemble("#ip 3
+/// addi 0 1 0
+/// gtri 0 4 1
+/// addr 1 3 3
+/// seti -1 0 3").unwrap();
+/// let mut profile = Profile::new(program.instructions.len());
+/// let mut machine = Machine::new(&program, Device::new(4)).unwrap();
+/// machine.run_while(|ip, _| profile.record(ip));
+/// assert_eq!(&[5, 5, 5, 4], profile.counts());
+/// assert_eq!(vec![(0, 5), (1, 5)], profile.hottest(2));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    counts: Vec<u64>,
+}
+
+impl Profile {
+    /// Create an empty profile for a program with the given number of
+    /// instructions.
+    pub fn new(len: usize) -> Self {
+        Profile {
+            counts: vec![0; len],
+        }
+    }
+
+    /// Count an execution of the instruction. Always returns true, so that it
+    /// can be used directly as the callback of `Machine::run_while`.
+    pub fn record(&mut self, ip: usize) -> bool {
+        self.counts[ip] += 1;
+        true
+    }
+
+    /// Execution count per instruction.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Total number of executed instructions.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The `n` most executed instructions with their counts, most executed
+    /// first.
+    pub fn hottest(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut counts: Vec<(usize, u64)> = self.counts.iter().cloned().enumerate().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts.truncate(n);
+        counts
+    }
+
+    /// Show the program with the execution count and share of every
+    /// instruction.
+    pub fn annotate<'a>(&'a self, program: &'a Program) -> Annotated<'a> {
+        Annotated {
+            profile: self,
+            program,
+        }
+    }
+}
+
+/// A program listing annotated with a profile, see `Profile::annotate`.
+pub struct Annotated<'a> {
+    profile: &'a Profile,
+    program: &'a Program,
+}
+
+impl<'a> fmt::Display for Annotated<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.profile.total().max(1) as f64;
+        for (i, ins) in self.program.instructions.iter().enumerate() {
+            let count = self.profile.counts.get(i).cloned().unwrap_or(0);
+            writeln!(
+                f,
+                "{:>12} {:>6.2}% {:3}: {}",
+                count,
+                100.0 * count as f64 / total,
+                i,
+                ins
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_binary_round_trip() {
+    use crate::day16::Device;
+    use crate::elfcode::vm::Machine;
+
+    let program = crate::elfcode::assemble(
+        "#ip 2
+seti -3 0 0
+addi 0 1 0
+gtri 0 -1 1
+addr 1 2 2
+seti 0 0 2",
+    )
+    .unwrap();
+    let mut recorder = Recorder::new(vec![], Format::Binary, 3).unwrap();
+    let mut entries = vec![];
+    let mut machine = Machine::new(&program, Device::new(3)).unwrap();
+    machine.run_traced(|ip, before, after| {
+        entries.push(Entry {
+            ip,
+            before: before.to_vec(),
+            after: after.to_vec(),
+        });
+        recorder.record(ip, before, after).is_ok()
+    });
+    let data = recorder.finish().unwrap();
+    assert_eq!(5 + entries.len() * (4 + 6 * 8), data.len());
+    assert_eq!(entries, read_binary(&data[..]).unwrap());
+    assert_eq!(
+        Entry {
+            ip: 1,
+            before: vec![-3, 0, 1],
+            after: vec![-2, 0, 1],
+        },
+        entries[1]
+    );
+    assert!(read_binary(&data[..data.len() - 1]).is_err());
+}
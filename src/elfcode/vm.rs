@@ -0,0 +1,365 @@
+use crate::day16::{Device, DeviceError, Operation, Program, Word};
+use crate::elfcode::idiom::{find_idioms, Idiom};
+
+/// Computes the value an instruction writes from the registers and its
+/// operands `a` and `b`.
+type Exec = fn(&[i64], i64, i64) -> i64;
+
+/// An instruction decoded for execution. Register operands have been checked
+/// against the number of registers in advance.
+#[derive(Clone, Copy)]
+struct Decoded {
+    exec: Exec,
+    a: i64,
+    b: i64,
+    c: usize,
+    /// Index of the idiom that starts at this instruction.
+    idiom: Option<u32>,
+}
+
+/// Fast interpreter for Elf-code programs.
+///
+/// The program is decoded once into function pointers and the registers are
+/// updated in place, so unlike `Program::run` nothing is allocated or checked
+/// while running. Invalid registers and unknown operations are reported when
+/// the machine is created instead of when they are executed.
+///
+/// # Examples
+///
+/// ```
+/// use aoc18::day16::Device;
+/// use aoc18::elfcode::assemble;
+/// use aoc18::elfcode::vm::Machine;
+///
+/// let program = assemble("#ip 3
+/// addi 0 1 0
+/// gtri 0 4 1
+/// addr 1 3 3
+/// seti -1 0 3").unwrap();
+/// let mut machine = Machine::new(&program, Device::new(4)).unwrap();
+/// machine.run();
+/// assert_eq!(5, machine.registers()[0]);
+/// assert_eq!(19, machine.steps());
+/// ```
+pub struct Machine {
+    code: Vec<Decoded>,
+    ip_register: Option<usize>,
+    ip: Option<usize>,
+    registers: Vec<i64>,
+    mask: i64,
+    steps: u64,
+    idioms: Vec<Idiom>,
+}
+
+impl Machine {
+    /// Decode the program for running on the device.
+    pub fn new(program: &Program, device: Device) -> Result<Self, DeviceError> {
+        let count = device.registers.len();
+        let register = |r: i64| {
+            if r < 0 || r as usize >= count {
+                Err(DeviceError::InvalidRegister { register: r, count })
+            } else {
+                Ok(r as usize)
+            }
+        };
+        if let Some(r) = program.ip {
+            register(r as i64)?;
+        }
+        let code = program
+            .instructions
+            .iter()
+            .map(|i| {
+                let (ra, rb) = i.op.register_operands();
+                if ra {
+                    register(i.a)?;
+                }
+                if rb {
+                    register(i.b)?;
+                }
+                Ok(Decoded {
+                    exec: decode(&i.op)?,
+                    a: i.a,
+                    b: i.b,
+                    c: register(i.c)?,
+                    idiom: None,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let ip = if code.is_empty() { None } else { Some(0) };
+        Ok(Machine {
+            code,
+            ip_register: program.ip,
+            ip,
+            registers: device.registers,
+            mask: match device.word {
+                Word::I64 => -1,
+                Word::WrappingU32 => i64::from(u32::MAX),
+            },
+            steps: 0,
+            idioms: vec![],
+        })
+    }
+
+    /// Decode the program like `new`, and compute the loops recognized by
+    /// `find_idioms` natively whenever execution reaches their first
+    /// instruction. The results and the number of steps are the same as
+    /// without it, but the callbacks of `run_while` and `run_traced` see such
+    /// a loop as a single instruction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use aoc18::day16::Device;
+    /// use aoc18::elfcode::assemble;
+    /// use aoc18::elfcode::vm::Machine;
+    ///
+    /// let program = assemble("#ip 3
+    /// addi 0 1 0
+    /// gtri 0 999999999 1
+    /// addr 1 3 3
+    /// seti -1 0 3").unwrap();
+    /// let mut machine = Machine::optimized(&program, Device::new(4)).unwrap();
+    /// machine.run();
+    /// assert_eq!(&[1_000_000_000, 1, 0, 3], machine.registers());
+    /// assert_eq!(3_999_999_999, machine.steps());
+    /// ```
+    pub fn optimized(program: &Program, device: Device) -> Result<Self, DeviceError> {
+        let mut machine = Machine::new(program, device)?;
+        machine.idioms = find_idioms(program);
+        for (k, idiom) in machine.idioms.iter().enumerate() {
+            machine.code[idiom.start].idiom = Some(k as u32);
+        }
+        Ok(machine)
+    }
+
+    /// Current values of the registers.
+    pub fn registers(&self) -> &[i64] {
+        &self.registers
+    }
+
+    /// Mutable access to the registers, for instance to set the input of a
+    /// program before running it.
+    pub fn registers_mut(&mut self) -> &mut [i64] {
+        &mut self.registers
+    }
+
+    /// Index of the next instruction, or `None` if the program halted.
+    pub fn ip(&self) -> Option<usize> {
+        self.ip
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The device with the current values of the registers.
+    pub fn device(&self) -> Device {
+        Device {
+            registers: self.registers.clone(),
+            word: if self.mask == -1 {
+                Word::I64
+            } else {
+                Word::WrappingU32
+            },
+        }
+    }
+
+    /// Run the program until it halts.
+    pub fn run(&mut self) {
+        self.run_while(|_, _| true);
+    }
+
+    /// Execute a single instruction, returning whether the program is still
+    /// running.
+    pub fn step(&mut self) -> bool {
+        let mut first = true;
+        self.run_while(|_, _| std::mem::replace(&mut first, false));
+        self.ip.is_some()
+    }
+
+    /// Run the program until it halts. Before every instruction `f` is called
+    /// with the index of the instruction and the registers as the instruction
+    /// will see them; execution stops early, right before that instruction,
+    /// when it returns false. Returns whether execution stopped early.
+    pub fn run_while<F>(&mut self, f: F) -> bool
+    where
+        F: FnMut(usize, &[i64]) -> bool,
+    {
+        self.execute(&mut Before(f))
+    }
+
+    /// Run the program until it halts. After every instruction `f` is called
+    /// with the index of the instruction and the registers before and after
+    /// executing it; execution stops early when it returns false. Returns
+    /// whether execution stopped early.
+    ///
+    /// This is slower than `run_while` because the registers are copied
+    /// before every instruction.
+    pub fn run_traced<F>(&mut self, f: F) -> bool
+    where
+        F: FnMut(usize, &[i64], &[i64]) -> bool,
+    {
+        self.execute(&mut Traced {
+            f,
+            before: Vec::with_capacity(self.registers.len()),
+        })
+    }
+
+    fn execute<H: Hook>(&mut self, hook: &mut H) -> bool {
+        let mut ip = match self.ip {
+            Some(ip) => ip,
+            None => return false,
+        };
+        let code = &self.code[..];
+        let registers = &mut self.registers[..];
+        let mask = self.mask;
+        let max = if mask == -1 { i64::MAX } else { mask };
+        let idioms = &self.idioms;
+        let mut steps = self.steps;
+        let stopped = loop {
+            if let Some(r) = self.ip_register {
+                registers[r] = ip as i64;
+            }
+            if !hook.before(ip, registers) {
+                break true;
+            }
+            let i = code[ip];
+            match i
+                .idiom
+                .and_then(|k| idioms[k as usize].apply(registers, max))
+            {
+                Some(n) => steps += n,
+                None => {
+                    registers[i.c] = (i.exec)(registers, i.a, i.b) & mask;
+                    steps += 1;
+                }
+            }
+            let next = match self.ip_register {
+                Some(r) => registers[r] + 1,
+                None => ip as i64 + 1,
+            };
+            let halted = next < 0 || next as usize >= code.len();
+            if !hook.after(ip, registers) {
+                if !halted {
+                    ip = next as usize;
+                }
+                break !halted;
+            }
+            if halted {
+                break false;
+            }
+            ip = next as usize;
+        };
+        self.steps = steps;
+        self.ip = if stopped { Some(ip) } else { None };
+        stopped
+    }
+}
+
+/// Callbacks around every executed instruction, returning whether to go on.
+trait Hook {
+    fn before(&mut self, ip: usize, registers: &[i64]) -> bool;
+    fn after(&mut self, ip: usize, registers: &[i64]) -> bool;
+}
+
+struct Before<F>(F);
+
+impl<F: FnMut(usize, &[i64]) -> bool> Hook for Before<F> {
+    fn before(&mut self, ip: usize, registers: &[i64]) -> bool {
+        (self.0)(ip, registers)
+    }
+
+    fn after(&mut self, _: usize, _: &[i64]) -> bool {
+        true
+    }
+}
+
+struct Traced<F> {
+    f: F,
+    before: Vec<i64>,
+}
+
+impl<F: FnMut(usize, &[i64], &[i64]) -> bool> Hook for Traced<F> {
+    fn before(&mut self, _: usize, registers: &[i64]) -> bool {
+        self.before.clear();
+        self.before.extend_from_slice(registers);
+        true
+    }
+
+    fn after(&mut self, ip: usize, registers: &[i64]) -> bool {
+        (self.f)(ip, &self.before, registers)
+    }
+}
+
+fn decode(op: &Operation) -> Result<Exec, DeviceError> {
+    Ok(match op {
+        Operation::ADDR => |r, a, b| r[a as usize].wrapping_add(r[b as usize]),
+        Operation::ADDI => |r, a, b| r[a as usize].wrapping_add(b),
+        Operation::MULR => |r, a, b| r[a as usize].wrapping_mul(r[b as usize]),
+        Operation::MULI => |r, a, b| r[a as usize].wrapping_mul(b),
+        Operation::BANR => |r, a, b| r[a as usize] & r[b as usize],
+        Operation::BANI => |r, a, b| r[a as usize] & b,
+        Operation::BORR => |r, a, b| r[a as usize] | r[b as usize],
+        Operation::BORI => |r, a, b| r[a as usize] | b,
+        Operation::SETR => |r, a, _| r[a as usize],
+        Operation::SETI => |_, a, _| a,
+        Operation::GTIR => |r, a, b| i64::from(a > r[b as usize]),
+        Operation::GTRI => |r, a, b| i64::from(r[a as usize] > b),
+        Operation::GTRR => |r, a, b| i64::from(r[a as usize] > r[b as usize]),
+        Operation::EQIR => |r, a, b| i64::from(a == r[b as usize]),
+        Operation::EQRI => |r, a, b| i64::from(r[a as usize] == b),
+        Operation::EQRR => |r, a, b| i64::from(r[a as usize] == r[b as usize]),
+        Operation::OP(o) => return Err(DeviceError::UnknownOperation(*o)),
+    })
+}
+
+#[test]
+fn test_matches_program_run() {
+    let program = crate::elfcode::assemble(
+        "#ip 2
+seti 10 0 1
+mulr 1 1 3
+bani 3 255 3
+addi 1 -1 1
+gtir 1 1 4
+addr 2 4 2
+seti 0 0 2
+eqri 3 36 0",
+    )
+    .unwrap();
+    for word in &[Word::I64, Word::WrappingU32] {
+        let device = Device::new(5).with_word(*word);
+        let mut machine = Machine::new(&program, device.clone()).unwrap();
+        machine.run();
+        assert_eq!(program.run(device).unwrap(), machine.device());
+        assert_eq!(None, machine.ip());
+        assert_eq!(1 + 10 * 6, machine.steps());
+    }
+}
+
+#[test]
+fn test_run_while_and_errors() {
+    let program = crate::elfcode::assemble("addi 0 1 0\naddi 0 1 0\naddi 0 1 0").unwrap();
+    let mut machine = Machine::new(&program, Device::new(1)).unwrap();
+    assert!(machine.run_while(|ip, _| ip != 2));
+    assert_eq!(Some(2), machine.ip());
+    assert_eq!(2, machine.registers()[0]);
+    assert!(!machine.step());
+    assert_eq!(3, machine.steps());
+    assert!(!machine.run_while(|_, _| false));
+
+    let program = crate::elfcode::assemble("#ip 1\nseti 0 0 0").unwrap();
+    assert_eq!(
+        Some(DeviceError::InvalidRegister {
+            register: 1,
+            count: 1
+        }),
+        Machine::new(&program, Device::new(1)).err()
+    );
+    let program = crate::elfcode::assemble("7 0 0 0").unwrap();
+    assert_eq!(
+        Some(DeviceError::UnknownOperation(7)),
+        Machine::new(&program, Device::new(1)).err()
+    );
+}
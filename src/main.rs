@@ -1,7 +1,16 @@
 use aoc18;
+use aoc18::day16::{Device, Program};
+use aoc18::elfcode::debugger::{Command, Debugger};
+use aoc18::elfcode::decompiler;
+use aoc18::elfcode::trace::{Format, Profile, Recorder};
+use aoc18::elfcode::vm::Machine;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::env;
-use std::io::{self, Read};
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
 use std::result::Result;
+use std::str::FromStr;
 
 fn read_stdin_and_report_result<T: std::fmt::Display>(f: fn(&str) -> T) {
     let mut buffer = String::new();
@@ -19,6 +28,127 @@ fn read_stdin_and_return_result<T>(f: fn(&str) -> T) -> Result<T, String> {
     }
 }
 
+fn read_program(path: &str) -> Result<Program, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    aoc18::elfcode::assemble(&source).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn debug(path: &str) {
+    let program = match read_program(path) {
+        Ok(p) => p,
+        Err(e) => return println!("{}", e),
+    };
+    let mut debugger = Debugger::new(program, Device::new(6));
+    println!("{}", debugger.apply(&Command::Registers));
+    let stdin = io::stdin();
+    loop {
+        print!("(elf) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => return println!("failed to read from STDIN: {}", e),
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Command::from_str(&line) {
+            Ok(Command::Quit) => break,
+            Ok(c) => println!("{}", debugger.apply(&c)),
+            Err(e) => println!("{} (try \"help\")", e),
+        }
+    }
+}
+
+/// Run a program with register 0 set to `r0`, computing the loops it
+/// recognizes natively.
+fn run(path: &str, r0: i64) {
+    let program = match read_program(path) {
+        Ok(p) => p,
+        Err(e) => return println!("{}", e),
+    };
+    let mut device = Device::new(6);
+    device.registers[0] = r0;
+    let mut machine = match Machine::optimized(&program, device) {
+        Ok(m) => m,
+        Err(e) => return println!("{}", e),
+    };
+    machine.run();
+    println!("{:?} after {} steps", machine.registers(), machine.steps());
+}
+
+/// Record the execution of a program into a trace file, as CSV if its name
+/// ends with `.csv` and in the binary format otherwise.
+fn trace(path: &str, output: &str, limit: u64) {
+    let program = match read_program(path) {
+        Ok(p) => p,
+        Err(e) => return println!("{}", e),
+    };
+    let device = Device::new(6);
+    let format = if output.ends_with(".csv") {
+        Format::Csv
+    } else {
+        Format::Binary
+    };
+    let file = match fs::File::create(output) {
+        Ok(f) => io::BufWriter::new(f),
+        Err(e) => return println!("failed to create {}: {}", output, e),
+    };
+    let mut recorder = match Recorder::new(file, format, device.registers.len()) {
+        Ok(r) => r,
+        Err(e) => return println!("failed to write {}: {}", output, e),
+    };
+    let mut machine = match Machine::new(&program, device) {
+        Ok(m) => m,
+        Err(e) => return println!("{}", e),
+    };
+    let mut error = None;
+    let mut steps = 0;
+    machine.run_traced(|ip, before, after| {
+        steps += 1;
+        if let Err(e) = recorder.record(ip, before, after) {
+            error = Some(e);
+        }
+        error.is_none() && steps < limit
+    });
+    if let Err(e) = recorder.finish() {
+        error = error.or(Some(e));
+    }
+    match error {
+        Some(e) => println!("failed to write {}: {}", output, e),
+        None => println!("recorded {} instructions", machine.steps()),
+    }
+}
+
+/// Show how often every instruction of a program is executed.
+fn profile(path: &str, limit: u64) {
+    let program = match read_program(path) {
+        Ok(p) => p,
+        Err(e) => return println!("{}", e),
+    };
+    let mut machine = match Machine::new(&program, Device::new(6)) {
+        Ok(m) => m,
+        Err(e) => return println!("{}", e),
+    };
+    let mut profile = Profile::new(program.instructions.len());
+    let mut steps = 0;
+    machine.run_while(|ip, _| {
+        steps += 1;
+        steps <= limit && profile.record(ip)
+    });
+    print!("{}", profile.annotate(&program));
+    println!("{} instructions", profile.total());
+}
+
+/// The optional maximum number of steps given as the n-th argument.
+fn step_limit(n: usize) -> u64 {
+    env::args()
+        .nth(n)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(u64::MAX)
+}
+
 fn main() {
     let command = &env::args().nth(1).unwrap() as &str;
     match command {
@@ -57,18 +187,108 @@ fn main() {
         "day12b" => {
             read_stdin_and_report_result(|x| aoc18::day12::sum_pots_after(x, 50_000_000_000))
         }
-        "day13a" => read_stdin_and_report_result(aoc18::day13::first_crash),
-        "day13b" => read_stdin_and_report_result(aoc18::day13::last_cart_location),
+        "day13a" => match read_stdin_and_return_result(aoc18::day13::first_crash) {
+            Ok(Ok(p)) => println!("{}", p),
+            Ok(Err(e)) => println!("{}", e),
+            Err(e) => println!("error: {}", e),
+        },
+        "day13b" => match read_stdin_and_return_result(aoc18::day13::last_cart_location) {
+            Ok(Ok(p)) => println!("{}", p),
+            Ok(Err(e)) => println!("{}", e),
+            Err(e) => println!("error: {}", e),
+        },
+        "day13check" => match read_stdin_and_return_result(aoc18::day13::validate) {
+            Ok(ref d) if d.is_empty() => println!("ok"),
+            Ok(diagnostics) => diagnostics.iter().for_each(|d| println!("{}", d)),
+            Err(e) => println!("error: {}", e),
+        },
+        "day13crashes" => match read_stdin_and_return_result(aoc18::day13::crashes) {
+            Ok(Ok(crashes)) => crashes.iter().for_each(|c| println!("{}", c)),
+            Ok(Err(diagnostics)) => diagnostics.iter().for_each(|d| println!("{}", d)),
+            Err(e) => println!("error: {}", e),
+        },
         "day14a" => read_stdin_and_report_result(aoc18::day14::score_after),
         "day14b" => read_stdin_and_report_result(aoc18::day14::num_recipes_before),
         "day15a" => read_stdin_and_report_result(aoc18::day15::combat_outcome),
         "day15b" => read_stdin_and_report_result(aoc18::day15::combat_outcome2),
+        "day15power" => read_stdin_and_report_result(|x| {
+            aoc18::day15::search_elf_power(x, aoc18::day15::Search::Linear, 4..=200)
+        }),
+        "day15gen" => {
+            let seed = env::args().nth(2).and_then(|s| s.parse().ok()).unwrap_or(0);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let options = aoc18::day15::CaveOptions::default();
+            match aoc18::day15::generate_cave(&options, &mut rng) {
+                Some(cave) => print!("{}", cave),
+                None => println!("failed to generate a cave"),
+            }
+        }
+        "day15log" => match read_stdin_and_return_result(|x| aoc18::day15::combat_log(x, 3)) {
+            Ok(log) => print!("{}", log),
+            Err(e) => println!("error: {}", e),
+        },
         "day16a" => read_stdin_and_report_result(aoc18::day16::num_samples),
         "day16b" => read_stdin_and_report_result(aoc18::day16::result_r0),
+        "day16gen" => {
+            let seed = env::args().nth(2).and_then(|s| s.parse().ok()).unwrap_or(0);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let map = aoc18::day16::random_opcode_map(&mut rng);
+            let options = aoc18::day16::GeneratorOptions::default();
+            match aoc18::day16::generate_input(&map, &options, &mut rng) {
+                Some(input) => print!("{}", input),
+                None => println!("failed to generate samples"),
+            }
+        }
         "day17a" => read_stdin_and_report_result(aoc18::day17::reservoir_reach),
         "day17b" => read_stdin_and_report_result(aoc18::day17::reservoir_capacity),
         "day18a" => read_stdin_and_report_result(aoc18::day18::resource_value),
+        "day20a" => read_stdin_and_report_result(aoc18::day20::furthest_room),
+        "day20b" => read_stdin_and_report_result(aoc18::day20::num_far_rooms),
+        "day21a" => read_stdin_and_report_result(aoc18::day21::fewest_instructions_r0),
+        "day21b" => read_stdin_and_report_result(aoc18::day21::most_instructions_r0),
+        "day22a" => read_stdin_and_report_result(aoc18::day22::risk_level),
+        "day22b" => read_stdin_and_report_result(aoc18::day22::rescue_time),
+        "day23a" => read_stdin_and_report_result(aoc18::day23::num_in_range),
+        "day23b" => read_stdin_and_report_result(aoc18::day23::best_position_dist),
+        "day24a" => read_stdin_and_report_result(aoc18::day24::winning_units),
+        "day24b" => match read_stdin_and_return_result(aoc18::day24::boosted_immune_units) {
+            Ok(Some(units)) => println!("{}", units),
+            Ok(None) => println!("no boost lets the immune system win"),
+            Err(e) => println!("error: {}", e),
+        },
         "day25a" => read_stdin_and_report_result(aoc18::day25::num_constellations),
+        "debug" => match env::args().nth(2) {
+            Some(path) => debug(&path),
+            None => println!("usage: debug <program>"),
+        },
+        "decompile" => match env::args().nth(2).map(|p| read_program(&p)) {
+            Some(Ok(program)) => print!("{}", decompiler::pseudo_code(&program)),
+            Some(Err(e)) => println!("{}", e),
+            None => println!("usage: decompile <program>"),
+        },
+        "cfg" => match env::args().nth(2).map(|p| read_program(&p)) {
+            Some(Ok(program)) => print!(
+                "{}",
+                decompiler::to_dot(&decompiler::control_flow_graph(&program))
+            ),
+            Some(Err(e)) => println!("{}", e),
+            None => println!("usage: cfg <program>"),
+        },
+        "run" => match env::args().nth(2) {
+            Some(path) => run(
+                &path,
+                env::args().nth(3).and_then(|s| s.parse().ok()).unwrap_or(0),
+            ),
+            None => println!("usage: run <program> [r0]"),
+        },
+        "trace" => match (env::args().nth(2), env::args().nth(3)) {
+            (Some(path), Some(output)) => trace(&path, &output, step_limit(4)),
+            _ => println!("usage: trace <program> <output.csv|output.bin> [max steps]"),
+        },
+        "profile" => match env::args().nth(2) {
+            Some(path) => profile(&path, step_limit(3)),
+            None => println!("usage: profile <program> [max steps]"),
+        },
         _ => println!("Unknown command: {}", command),
     }
 }